@@ -1,121 +1,5241 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::{
-    collections::HashSet,
-    ffi::OsStr,
+    cmp::Ordering,
+    collections::{BTreeMap, HashSet, VecDeque},
+    env,
+    ffi::{OsStr, OsString},
+    fmt,
     fs::{self, File},
-    io::Read,
-    path::{Path, PathBuf},
-    process::{Command, ExitStatus, Stdio},
+    io::{self, BufRead, BufReader, IsTerminal, Read, Seek, Write},
+    os::unix::fs::DirBuilderExt,
+    path::{self, Path, PathBuf},
+    process::{Command, ExitCode, ExitStatus, Stdio},
+    sync::{Arc, Mutex, OnceLock},
+    thread,
+    time::{Duration, Instant},
 };
 
 use anyhow::{Result, anyhow, bail};
-use directories::ProjectDirs;
-use serde::{Deserialize, Serialize};
+use directories::{BaseDirs, ProjectDirs};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
 const APP_NAME: &str = env!("CARGO_PKG_NAME");
 const WINEPREFIX: &str = "WINEPREFIX";
+const WINEDLLOVERRIDES: &str = "WINEDLLOVERRIDES";
+const INSTALLER_NAMES: [&str; 3] = ["setup", "install", "autorun"];
+const COPIED_EXE_DIR: &str = "drive_c/installers";
+/// Entries which wineboot creates in a prefix.
+const PREFIX_ENTRIES: [&str; 6] = [
+    "system.reg",
+    "user.reg",
+    "userdef.reg",
+    "dosdevices",
+    "drive_c/windows/system32",
+    "drive_c/users",
+];
+/// Directories of the data dir which are not prefixes.
+const DATA_DIR_ENTRIES: [&str; 3] = ["named", "downloads", "default-prefix"];
+/// Directory in prefixes_dir where prefixes are created before they are renamed into place.
+const CREATING_EXEC_ENV_DIR: &str = ".creating";
+const EXE_PLACEHOLDER: &str = "{exe}";
+const WINE_PLACEHOLDER: &str = "{wine}";
+const ARGS_PLACEHOLDER: &str = "{args...}";
+const STDERR_TAIL_LINES: usize = 20;
+/// Size of wine-trace.log after which it is rotated, and the number of kept files.
+const WINE_TRACE_MAX_BYTES: u64 = 64 * 1024 * 1024;
+const WINE_TRACE_FILES: usize = 3;
+const FD_LIMIT_ERRORS: [&str; 2] = ["Too many open files", "file descriptor limit"];
+static COMMAND_LEDGER: OnceLock<PathBuf> = OnceLock::new();
+static COLOR: OnceLock<bool> = OnceLock::new();
+static ASSUME_YES: OnceLock<bool> = OnceLock::new();
+/// Time to wait for the lock of an exec env. (--lock-timeout)
+static LOCK_TIMEOUT: OnceLock<Duration> = OnceLock::new();
+const EXIT_CODES_HELP: &str = "Exit codes:
+  0       success
+  1       other failure
+  2       invalid command line
+  3       winetricks failed
+  4       wine failed with an exit code below 10, which is shown in the error, or by a signal
+  5       a required program is not found
+  6       invalid configuration
+  7       a command timed out
+  8       another process keeps using the prefix (--lock-timeout)
+  9       canceled by the user
+  10-255  exit code of wine";
+/// Patterns of host environment variables which are kept by --clean-env.
+static ENV_PASSTHROUGH: OnceLock<Vec<String>> = OnceLock::new();
+const ALWAYS_PASSED_ENVS: [&str; 2] = ["PATH", "HOME"];
+/// User who runs wine and winetricks of the prefix with sudo. (--reexec-as)
+static RUN_AS: OnceLock<String> = OnceLock::new();
+/// Host environment variables which are kept through sudo for the display.
+const DISPLAY_ENVS: [&str; 4] = [
+    "DISPLAY",
+    "WAYLAND_DISPLAY",
+    "XAUTHORITY",
+    "XDG_RUNTIME_DIR",
+];
+/// X display of Xvfb started by --headless, which wine and winetricks use.
+static HEADLESS_DISPLAY: OnceLock<String> = OnceLock::new();
+/// Lock of the loaded exec env. It serializes read-modify-write of conf.toml between processes.
+static EXEC_ENV_LOCK: Mutex<Option<File>> = Mutex::new(None);
+/// Metrics of the current run which are collected only with --metrics-file.
+static RUN_METRICS: Mutex<Option<RunMetrics>> = Mutex::new(None);
+const STEAM_COMPAT_DATA_PATH: &str = "STEAM_COMPAT_DATA_PATH";
+const STEAM_COMPAT_CLIENT_INSTALL_PATH: &str = "STEAM_COMPAT_CLIENT_INSTALL_PATH";
+const WINETRICKS_VERBS_CACHE: &str = "winetricks-list-all.txt";
+const ENV_CONFIG_PREFIX: &str = "WINE_ISO_RUN_";
+const WINETRICKS_VERBS_CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
 
+/// config.toml. Each field can be overridden by the environment variable WINE_ISO_RUN_<FIELD>
+/// (e.g. WINE_ISO_RUN_ARCH_DEFAULT=win32), and command line options override both of them.
 #[derive(Serialize, Deserialize, Debug)]
 struct Config {
     data_dir: Option<PathBuf>,
+    /// Directory where prefixes are created. (default: data_dir)
+    prefixes_dir: Option<PathBuf>,
+    /// Disable esync and fsync for all prefixes.
+    #[serde(default)]
+    disable_esync: bool,
+    /// Prerequisites of winetricks commands. (overrides the built-in ones)
+    #[serde(default)]
+    trick_dependencies: BTreeMap<String, Vec<String>>,
+    /// Command line which launches exe. `{wine}` and `{exe}` are required, and `{args...}` is
+    /// replaced with the arguments of exe. (e.g. ["gamemoderun", "{wine}", "{exe}", "{args...}"])
+    #[serde(default)]
+    wine_command_template: Vec<String>,
+    /// Directory where logs of prefixes are written instead of the prefix directories.
+    output_dir: Option<PathBuf>,
+    /// Free space of prefixes_dir below which creating a new prefix is warned. (MiB)
+    #[serde(default = "default_min_free_space_mib")]
+    min_free_space_mib: u64,
+    /// Architecture of new prefixes when --arch is not given and exe does not require win64.
+    arch_default: Option<WineArch>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct ExecEnv {
+    /// Executed winetricks commands in the order of execution.
+    #[serde(default)]
+    executed_tricks: Vec<String>,
+    #[serde(default)]
+    dll_overrides: BTreeMap<String, String>,
+    /// Runner of the prefix, which is recorded as the path to the proton script of proton.
+    #[serde(default, rename = "proton")]
+    runner: PrefixRunner,
+    #[serde(default)]
+    disable_esync: bool,
+    /// Locale for wine and winetricks.
+    #[serde(default)]
+    lang: Option<String>,
+    /// Whether wine mono is installed. None means wine decides it.
+    #[serde(default)]
+    mono: Option<bool>,
+    /// Whether wine gecko is installed. None means wine decides it.
+    #[serde(default)]
+    gecko: Option<bool>,
+    /// Directories added to WINEPATH. (host paths or windows paths)
+    #[serde(default)]
+    winepath: Vec<String>,
+    /// Directories added to WINEDLLPATH.
+    #[serde(default)]
+    winedllpath: Vec<PathBuf>,
+    /// Location of exe copied into the prefix by --copy-exe-into-prefix.
+    #[serde(default)]
+    copied_exe: Option<PathBuf>,
+    /// Wine prefix outside of the exec env. (the default prefix of --use-default-prefix)
+    #[serde(default)]
+    wine_prefix: Option<PathBuf>,
+    /// Seconds which each executed winetricks command took.
+    #[serde(default)]
+    trick_durations: BTreeMap<String, f64>,
+    /// Windows environment variables written to HKCU\Environment of the prefix.
+    #[serde(default)]
+    win_envs: BTreeMap<String, String>,
+    /// Architecture of the prefix. None means wine decides it.
+    #[serde(default)]
+    arch: Option<WineArch>,
+    /// Snapshots of the wine prefix in the order of creation.
+    #[serde(default)]
+    snapshots: Vec<Snapshot>,
+    /// User who owns the wine prefix and runs wine and winetricks in it. (--reexec-as)
+    #[serde(default)]
+    run_as: Option<String>,
+    /// Symlink to the wine prefix which is kept up to date. (--wineprefix-symlink)
+    #[serde(default)]
+    wineprefix_symlink: Option<PathBuf>,
+}
+
+/// Copy of the wine prefix under the snapshots dir of the exec env, with the records which
+/// are restored together with it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Snapshot {
+    name: String,
+    /// Seconds since the unix epoch.
+    created: u64,
+    #[serde(default)]
+    executed_tricks: Vec<String>,
+    #[serde(default)]
+    win_envs: BTreeMap<String, String>,
+}
+
+/// Resolved winetricks commands of a prefix in the order of installation.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct TricksLock {
+    winetricks_version: String,
+    #[serde(default)]
+    tricks: Vec<String>,
+}
+
+/// Category of failure which decides the exit code. The codes are stable. (EXIT_CODES_HELP)
+/// 1 is any other failure and 2 is an invalid command line.
+#[derive(Debug, Clone, Copy)]
+enum Failure {
+    /// 3: winetricks is not succeed.
+    Winetricks,
+    /// wine is not succeed. The exit code of wine is passed through when it is 10 or more so that
+    /// it is not taken as another category, or 4.
+    Wine(Option<i32>),
+    /// 5: a required program is not found.
+    MissingProgram,
+    /// 6: config.toml, conf.toml, tricks.lock or another configuration is invalid.
+    Config,
+    /// 7: a command does not finish within the timeout.
+    Timeout,
+    /// 8: the exec env is locked by another process longer than --lock-timeout.
+    Locked,
+    /// 9: canceled by the user.
+    Canceled,
+}
+impl Failure {
+    fn exit_code(self) -> u8 {
+        match self {
+            Failure::Winetricks => 3,
+            Failure::Wine(code) => code
+                .and_then(|code| u8::try_from(code).ok())
+                .filter(|code| *code >= 10)
+                .unwrap_or(4),
+            Failure::MissingProgram => 5,
+            Failure::Config => 6,
+            Failure::Timeout => 7,
+            Failure::Locked => 8,
+            Failure::Canceled => 9,
+        }
+    }
+}
+#[derive(Debug)]
+struct FailureError {
+    failure: Failure,
+    message: String,
+}
+impl fmt::Display for FailureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)?;
+        // the exit code of wine which is not passed through is told in the message.
+        if let Failure::Wine(Some(code)) = self.failure
+            && i32::from(self.failure.exit_code()) != code
+        {
+            write!(
+                f,
+                " (exit code {code} of wine is reported as {})",
+                self.failure.exit_code()
+            )?;
+        }
+        Ok(())
+    }
+}
+impl std::error::Error for FailureError {}
+fn fail(failure: Failure, message: impl fmt::Display) -> anyhow::Error {
+    anyhow::Error::new(FailureError {
+        failure,
+        message: message.to_string(),
+    })
+}
+
+#[derive(Parser, Debug)]
+#[command(version,about,long_about = None, after_help = EXIT_CODES_HELP)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+    #[command(flatten)]
+    run: Args,
+    /// Disable colored output. (NO_COLOR is respected too)
+    #[arg(long, global = true)]
+    no_color: bool,
+    /// Answer yes to all confirmations.
+    #[arg(short, long, visible_alias = "assume-yes", global = true)]
+    yes: bool,
+    /// Fail when another process keeps using the prefix for the seconds, instead of waiting.
+    #[arg(long, value_name = "SECS", global = true)]
+    lock_timeout: Option<u64>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Run winetricks commands for the prefix of exe without launching it.
+    Tricks {
+        /// Path to exe file.
+        exec_path: PathBuf,
+        /// Winetricks commands. (comma separated or space separated)
+        #[arg(required = true)]
+        tricks: Vec<String>,
+        #[command(flatten)]
+        tricks_args: TricksArgs,
+    },
+    /// List winetricks verbs with the executed and installed ones of the prefix of exe.
+    ListTricks {
+        /// Path to exe file.
+        exec_path: PathBuf,
+        /// Show only verbs of the category. (e.g. dlls, fonts, settings)
+        #[arg(long)]
+        category: Option<String>,
+        /// Show only executed or installed verbs.
+        #[arg(long)]
+        installed_only: bool,
+    },
+    /// Use the prefix of old exe for new exe, such as after renaming exe.
+    Relink {
+        /// Path to exe file which has the prefix.
+        old_exec_path: PathBuf,
+        /// Path to exe file which uses the prefix.
+        new_exec_path: PathBuf,
+        /// Move the prefix to new exe instead of sharing it.
+        #[arg(long = "move")]
+        move_prefix: bool,
+    },
+    /// Find exe files under a directory and show which of them have prefixes.
+    ListPrefixesFor {
+        /// Directory to search.
+        dir: PathBuf,
+        /// Maximum depth of subdirectories to search.
+        #[arg(long)]
+        max_depth: Option<usize>,
+        /// Print as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Fix prefix links of relink and --prefix-name whose prefixes are missing.
+    RepairLinks {
+        /// Fix without asking. This or --yes is required when stdin is not a terminal.
+        #[arg(long)]
+        auto: bool,
+    },
+    /// Print export commands of the prefix environment of exe for the shell.
+    /// (e.g. eval "$(wine-iso-run shell-env game.exe)")
+    ShellEnv {
+        /// Path to exe file.
+        exec_path: PathBuf,
+    },
+    /// Start $SHELL with the prefix environment of exe.
+    Shell {
+        /// Path to exe file.
+        exec_path: PathBuf,
+    },
+    /// Show the headers of 32-bit or 64-bit windows exe without launching it.
+    Probe {
+        /// Path to exe file.
+        exec_path: PathBuf,
+        /// Print as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Save a copy of the prefix of exe, which is a reflink copy when the filesystem supports it.
+    Snapshot {
+        /// Path to exe file.
+        exec_path: PathBuf,
+        /// Name of the snapshot. (default: snapshot-N)
+        #[arg(value_parser = parse_prefix_name)]
+        name: Option<String>,
+    },
+    /// Restore the prefix of exe from a snapshot.
+    Rollback {
+        /// Path to exe file.
+        exec_path: PathBuf,
+        /// Name of the snapshot. (default: the latest one)
+        name: Option<String>,
+    },
+    /// Stop wineserver and the wine processes of the prefix of exe.
+    Stop {
+        /// Path to exe file.
+        exec_path: PathBuf,
+    },
+    /// Check that the prefix of exe is healthy, and exit with failure if it is not.
+    Verify {
+        /// Path to exe file.
+        exec_path: PathBuf,
+    },
+    /// Show directories which no prefix uses with their sizes, without deleting them.
+    /// (interrupted creations, named prefixes without links and unrecorded snapshots)
+    ListOrphanDirs {
+        /// Print as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the data dir which is used with the current config.toml.
+    DataDir,
+    /// Summarize the data dir, the prefixes and the wine tools.
+    Status {
+        /// Print as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Forget executed winetricks commands without touching the prefix.
+    PruneTricks {
+        /// Path to exe file.
+        exec_path: PathBuf,
+        /// Winetricks commands to forget. (comma separated or space separated)
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
+        tricks: Vec<String>,
+        /// Forget all executed winetricks commands.
+        #[arg(long)]
+        all: bool,
+    },
+}
+
+/// Version of wine such as 9.0 or 9.0-rc2, where a release candidate comes before the release.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct WineVersion {
+    numbers: Vec<u64>,
+    rc: Option<u64>,
+}
+
+impl WineVersion {
+    /// Parse the leading version of text. (e.g. "wine-9.0 (Staging)", "9.0-rc2", "8.0.2")
+    fn parse(text: &str) -> Option<WineVersion> {
+        let text = text.trim();
+        let text = text.strip_prefix("wine-").unwrap_or(text);
+        let end = text
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(text.len());
+        let numbers = text[..end]
+            .split('.')
+            .map(|number| number.parse().ok())
+            .collect::<Option<Vec<u64>>>()?;
+        let rc = text[end..].strip_prefix("-rc").and_then(|rest| {
+            rest.chars()
+                .take_while(|c| c.is_ascii_digit())
+                .collect::<String>()
+                .parse()
+                .ok()
+        });
+        Some(WineVersion { numbers, rc })
+    }
+    fn compare(&self, other: &WineVersion) -> Ordering {
+        let len = self.numbers.len().max(other.numbers.len());
+        let number = |version: &WineVersion, i| version.numbers.get(i).copied().unwrap_or(0);
+        (0..len)
+            .map(|i| number(self, i).cmp(&number(other, i)))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| match (self.rc, other.rc) {
+                (None, None) => Ordering::Equal,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (Some(rc), Some(other_rc)) => rc.cmp(&other_rc),
+            })
+    }
+}
+
+/// Constraints of --wine-version-require which all have to be satisfied.
+#[derive(Debug, Clone)]
+struct VersionConstraint {
+    text: String,
+    requirements: Vec<(String, WineVersion)>,
+}
+
+impl VersionConstraint {
+    fn is_satisfied(&self, version: &WineVersion) -> bool {
+        self.requirements.iter().all(|(op, required)| {
+            let ordering = version.compare(required);
+            match op.as_str() {
+                ">=" => ordering.is_ge(),
+                ">" => ordering.is_gt(),
+                "<=" => ordering.is_le(),
+                "<" => ordering.is_lt(),
+                _ => ordering.is_eq(),
+            }
+        })
+    }
+}
+
+/// Runner selected by --runner.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum RunnerKind {
+    Wine,
+    Proton,
+}
+
+impl RunnerKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            RunnerKind::Wine => "wine",
+            RunnerKind::Proton => "proton",
+        }
+    }
+}
+
+/// WINEARCH of a prefix, which can not be changed after the prefix is created.
+#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum WineArch {
+    Win32,
+    Win64,
+}
+
+impl WineArch {
+    fn as_str(self) -> &'static str {
+        match self {
+            WineArch::Win32 => "win32",
+            WineArch::Win64 => "win64",
+        }
+    }
+}
+
+/// Windows priority class which is applied inside wine with `start`.
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+enum WinPriority {
+    #[value(alias = "idle")]
+    Low,
+    BelowNormal,
+    Normal,
+    AboveNormal,
+    High,
+    Realtime,
+}
+
+impl WinPriority {
+    fn start_option(self) -> &'static str {
+        match self {
+            WinPriority::Low => "/low",
+            WinPriority::BelowNormal => "/belownormal",
+            WinPriority::Normal => "/normal",
+            WinPriority::AboveNormal => "/abovenormal",
+            WinPriority::High => "/high",
+            WinPriority::Realtime => "/realtime",
+        }
+    }
+}
+
+/// Fields of a windows shortcut (.lnk) which are needed to run its target.
+#[derive(Debug, Default)]
+struct ShellLink {
+    /// Windows path of the target.
+    target: Option<String>,
+    /// Target path relative to the shortcut.
+    relative_path: Option<String>,
+    working_dir: Option<String>,
+    arguments: Option<String>,
+}
+
+/// Format of exe which is told by the header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExeKind {
+    /// 32-bit or 64-bit windows exe.
+    Pe,
+    /// 16-bit windows exe.
+    Ne,
+    /// DOS exe or unknown format.
+    Other,
+}
+
+/// Headers of PE exe and the hints of its manifest.
+#[derive(Serialize, Debug)]
+struct PeInfo {
+    machine: String,
+    bits: u32,
+    subsystem: String,
+    linker_version: String,
+    os_version: String,
+    subsystem_version: String,
+    /// requestedExecutionLevel of the manifest. (e.g. asInvoker, requireAdministrator)
+    execution_level: Option<String>,
+    /// Windows versions in supportedOS of the manifest.
+    supported_os: Vec<String>,
+}
+
+/// Removes the prefix created by this run when it is dropped before the setup finishes.
+struct NewPrefixGuard {
+    /// Prefix dirs and links to remove. Empty when nothing is created.
+    paths: Vec<PathBuf>,
+    keep: bool,
+}
+
+impl NewPrefixGuard {
+    fn finish(mut self) {
+        self.paths.clear();
+    }
+}
+
+impl Drop for NewPrefixGuard {
+    fn drop(&mut self) {
+        let Some(exec_env_path) = self.paths.first() else {
+            return;
+        };
+        if self.keep {
+            println!(
+                "Keep the failed new prefix {} for inspection.",
+                exec_env_path.display()
+            );
+            return;
+        }
+        println!("Remove the failed new prefix {}", exec_env_path.display());
+        for path in &self.paths {
+            let removed = if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_symlink())
+            {
+                fs::remove_file(path)
+            } else {
+                fs::remove_dir_all(path)
+            };
+            if let Err(e) = removed {
+                warn(format!("Can not remove {}: {e}", path.display()));
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Gpu {
+    Discrete,
+    Integrated,
+    Device(String),
+}
+
+#[derive(clap::Args, Debug, Clone, Default)]
+struct TricksArgs {
+    /// Run winetricks commands without recording them as executed.
+    #[arg(long)]
+    no_tricks_record: bool,
+    /// Argument passed to every winetricks invocation before the command. (e.g. --force)
+    #[arg(long, allow_hyphen_values = true)]
+    winetricks_arg: Vec<String>,
+    /// Show winetricks commands and ask for confirmation before running them.
+    #[arg(long)]
+    confirm_tricks: bool,
+    /// Record every command invocation to commands.log in the output directory.
+    #[arg(long)]
+    trace_commands: bool,
+    /// Directory where logs of the prefix are written. (default: output_dir of config, or the
+    /// prefix directory)
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
+    /// Run wine and winetricks without the host environment variables except PATH, HOME and
+    /// --env-passthrough.
+    #[arg(long)]
+    clean_env: bool,
+    /// Host environment variable kept by --clean-env. `*` and `?` are wildcards. (e.g. XDG_*)
+    #[arg(long, requires = "clean_env")]
+    env_passthrough: Vec<String>,
+    /// Refuse to run when winetricks commands or version do not match tricks.lock.
+    #[arg(long)]
+    locked: bool,
+    /// Record the current winetricks version in tricks.lock instead of the version which the
+    /// prefix is built with.
+    #[arg(long, conflicts_with = "locked")]
+    update_tricks_lock: bool,
+    /// Fail before running anything when a winetricks command is unknown to winetricks.
+    #[arg(long)]
+    strict_tricks: bool,
+    /// Kill a winetricks command and its wineserver when it runs longer than the seconds.
+    #[arg(long, value_name = "SECONDS")]
+    winetricks_verb_timeout: Option<u64>,
+    /// Continue with the next winetricks command when one fails or times out.
+    #[arg(long)]
+    keep_going: bool,
+    /// Run wine and winetricks on a virtual X display of Xvfb. (for CI without display)
+    /// --display of exe takes precedence over it.
+    #[arg(long)]
+    headless: bool,
+    /// Environment variable only for winetricks, not for exe. (e.g. W_OPT_UNATTENDED=1)
+    #[arg(long, value_parser = parse_env)]
+    winetricks_env: Vec<(String, String)>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct Args {
+    /// Run winetricks commands when it is not yet executed.
+    #[arg(long)]
+    with_tricks: Vec<String>,
+    /// Run all winetricks verbs of the category which are not yet executed. (e.g. fonts)
+    #[arg(long)]
+    tricks_category: Vec<String>,
+    #[command(flatten)]
+    tricks_args: TricksArgs,
+    /// Set up the prefix and run winetricks without launching the exe.
+    #[arg(long)]
+    install_only: bool,
+    /// Override dll load order for wine. (e.g. d3d9=native,builtin)
+    #[arg(long, value_parser = parse_dll_override)]
+    dll_override: Vec<(String, String)>,
+    /// Run exe with proton instead of wine. (path to proton or name of installed proton)
+    #[arg(long)]
+    proton: Option<String>,
+    /// Make a symlink at PATH to the wine prefix, such as for file managers and other wine tools.
+    /// It is recorded for the prefix and follows the prefix when it is moved.
+    #[arg(long, value_name = "PATH")]
+    wineprefix_symlink: Option<PathBuf>,
+    /// Runner of the prefix. proton needs --proton unless the prefix has proton, and changing
+    /// a proton prefix to wine needs --reset-prefix.
+    #[arg(long, value_enum)]
+    runner: Option<RunnerKind>,
+    /// Locale for wine and winetricks of the prefix. (e.g. ja_JP.UTF-8)
+    #[arg(long, value_parser = parse_lang)]
+    lang: Option<String>,
+    /// Suppress installing wine mono for the prefix.
+    #[arg(long, conflicts_with = "install_mono")]
+    no_mono: bool,
+    /// Suppress installing wine gecko for the prefix.
+    #[arg(long, conflicts_with = "install_gecko")]
+    no_gecko: bool,
+    /// Install wine mono into the prefix with wineboot.
+    #[arg(long)]
+    install_mono: bool,
+    /// Install wine gecko into the prefix with wineboot.
+    #[arg(long)]
+    install_gecko: bool,
+    /// Disable esync and fsync for the prefix. (for environments with low file descriptor limit)
+    #[arg(long)]
+    disable_esync: bool,
+    /// Copy exe into the prefix and run the copy. (for installers that delete themselves)
+    #[arg(long)]
+    copy_exe_into_prefix: bool,
+    /// Run wine inside bubblewrap sandbox which hides the home directory.
+    #[arg(long)]
+    sandbox: bool,
+    /// Additional directory which is bound read-write in the sandbox.
+    #[arg(long, requires = "sandbox")]
+    sandbox_bind: Vec<PathBuf>,
+    /// Recreate the wine prefix and replay executed winetricks commands.
+    #[arg(long)]
+    reset_prefix: bool,
+    /// Fail instead of warning when the free space is low for a new prefix.
+    #[arg(long)]
+    strict_free_space: bool,
+    /// Run exe in the default wine prefix. ($WINEPREFIX or ~/.wine)
+    #[arg(long, conflicts_with_all = ["proton", "reset_prefix"])]
+    use_default_prefix: bool,
+    /// Run exe in the named prefix under the data dir, which is shared by exes of the same name.
+    /// exe keeps using it in later runs.
+    #[arg(long, value_parser = parse_prefix_name, conflicts_with = "use_default_prefix")]
+    prefix_name: Option<String>,
+    /// Architecture of a new prefix. (default: win64 for 64-bit exe, then arch_default of config,
+    /// then the default of wine)
+    #[arg(long, value_enum, conflicts_with_all = ["proton", "use_default_prefix"])]
+    arch: Option<WineArch>,
+    /// Run wine and winetricks of the prefix as the user with sudo, and give the wine prefix to
+    /// the user. Later runs of the prefix keep using the user. The exe and the prefix must be
+    /// reachable by the user.
+    #[arg(long, value_name = "USER", conflicts_with = "use_default_prefix")]
+    reexec_as: Option<String>,
+    /// Keep a new prefix on disk when its setup fails in this run, for inspection.
+    #[arg(long, conflicts_with = "on_failure_remove")]
+    on_failure_keep: bool,
+    /// Remove a new prefix when its setup fails in this run. (default)
+    /// Prefixes which already exist are never removed.
+    #[arg(long)]
+    on_failure_remove: bool,
+    /// Fail when the prefix of exe is already created. (for scripts which create fresh prefixes)
+    #[arg(long, conflicts_with = "use_default_prefix")]
+    abort_on_existing_prefix: bool,
+    /// Fail when the prefix of exe is not created yet.
+    #[arg(
+        long,
+        conflicts_with_all = ["use_default_prefix", "abort_on_existing_prefix"]
+    )]
+    require_existing_prefix: bool,
+    /// Print how the prefix of exe is selected.
+    #[arg(long)]
+    verbose_resolution: bool,
+    /// Wait until wineserver of the prefix exits after exe exits.
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    wait_wineserver: bool,
+    /// Keep wineserver of the prefix running after exe exits so that later runs start faster.
+    /// It holds the resources of the prefix until `stop` is run. Implies --wait-wineserver false.
+    #[arg(long, conflicts_with = "sandbox")]
+    wineserver_persist: bool,
+    /// Save the screen of exe to screenshot.png in the output directory when exe exits.
+    /// The screen is captured every few seconds with import (X) or grim (wayland).
+    #[arg(long)]
+    capture_screenshot_on_exit: bool,
+    /// Print the effective configuration and the launch command as JSON without running.
+    #[arg(long)]
+    show_config: bool,
+    /// Do not print information about prefix creation.
+    #[arg(short, long)]
+    quiet: bool,
+    /// Environment variable for wine. (e.g. KEY=VALUE)
+    #[arg(long, value_parser = parse_env)]
+    env: Vec<(String, String)>,
+    /// Windows environment variable saved in the registry of the prefix. (e.g. KEY=VALUE)
+    /// This is for programs which do not see --env.
+    #[arg(long, value_parser = parse_env)]
+    win_env: Vec<(String, String)>,
+    /// Directory added to WINEPATH of the prefix. (host path or windows path)
+    #[arg(long)]
+    winepath: Vec<String>,
+    /// Directory of dlls added to WINEDLLPATH of the prefix.
+    #[arg(long)]
+    winedllpath: Vec<PathBuf>,
+    /// Windows priority class of exe inside wine. This is not the niceness of the host process.
+    #[arg(long, value_enum)]
+    win_priority: Option<WinPriority>,
+    /// X display of exe. (e.g. :1)
+    #[arg(long, value_parser = parse_display)]
+    display: Option<String>,
+    /// Wayland display of exe. (e.g. wayland-1)
+    #[arg(long)]
+    wayland_display: Option<String>,
+    /// Debug channels of wine for exe. (e.g. --wine-trace=+relay, default: +relay,+seh)
+    /// stderr of exe is written to the rotated wine-trace.log in the output directory instead of
+    /// the console.
+    #[arg(
+        long,
+        value_name = "CHANNELS",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "+relay,+seh"
+    )]
+    wine_trace: Option<String>,
+    /// Refuse to run when the version of wine does not satisfy the constraint.
+    /// Constraints are separated by commas. (e.g. ">=9.0", ">=8.0,<10")
+    #[arg(long, value_name = "CONSTRAINT", value_parser = parse_version_constraint)]
+    wine_version_require: Option<VersionConstraint>,
+    /// Write metrics of the run to the file as JSON.
+    /// (durations of winetricks and wine, installed verbs, prefix size and exit code)
+    #[arg(long, value_name = "PATH")]
+    metrics_file: Option<PathBuf>,
+    /// GPU to run exe on. (discrete, integrated or device name such as 10de:1c8d)
+    #[arg(long, value_parser = parse_gpu)]
+    gpu: Option<Gpu>,
+    /// Load environment variables for wine from dotenv file. (overridden by --env)
+    #[arg(long)]
+    env_file: Option<PathBuf>,
+    /// Expected SHA-256 of exe. Refuse to run when it does not match.
+    #[arg(long, value_parser = parse_sha256)]
+    sha256: Option<String>,
+    /// Run this exe in the same prefix after the installer exits successfully.
+    /// (windows path, or host path which is relative to drive_c of the prefix)
+    #[arg(long)]
+    after_install_run: Option<String>,
+    /// Read arguments for exe from the file. They are placed before the positional arguments.
+    /// Arguments are separated by whitespaces and lines starting with # are comments.
+    /// Single quotes keep the text as is, and a backslash escapes the next character outside
+    /// quotes or `"` and `\` in double quotes.
+    #[arg(long)]
+    args_file: Option<PathBuf>,
+    /// Replace backslashes in the exe path with slashes, such as App\game.exe of scripts for
+    /// windows. A path which exists as it is or has a drive letter is kept.
+    #[arg(long)]
+    replace_path_sep: bool,
+    /// Download exe from URL into the data dir and run it. Arguments for exe are given by
+    /// --args-file since exe path is not given.
+    #[arg(long)]
+    download: Option<String>,
+    /// Path to exe file, or directory of setup files which contains the installer.
+    #[arg(required_unless_present = "download", conflicts_with = "download")]
+    exec_path: Option<PathBuf>,
+    /// Arguments for exe. If any argument contains {exe}, it is replaced with the exe path
+    /// and the exe path is not prepended.
+    /// Arguments after the first one or after -- are all passed to exe even if they look like
+    /// flags. (e.g. wine-iso-run game.exe -- --fullscreen)
+    #[arg(trailing_var_arg = true)]
+    args: Vec<String>,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    COLOR.get_or_init(|| {
+        !cli.no_color
+            && env::var_os("NO_COLOR").is_none_or(|no_color| no_color.is_empty())
+            && io::stderr().is_terminal()
+    });
+    ASSUME_YES.get_or_init(|| cli.yes);
+    if let Some(lock_timeout) = cli.lock_timeout {
+        LOCK_TIMEOUT.get_or_init(|| Duration::from_secs(lock_timeout));
+    }
+    match run_cli(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{} {error:?}", paint("Error:", "31"));
+            ExitCode::from(error_exit_code(&error))
+        }
+    }
+}
+fn error_exit_code(error: &anyhow::Error) -> u8 {
+    error
+        .chain()
+        .find_map(|e| e.downcast_ref::<FailureError>())
+        .map_or(1, |e| e.failure.exit_code())
+}
+fn run_cli(cli: Cli) -> Result<()> {
+    match cli.command {
+        Some(Commands::Tricks {
+            exec_path,
+            tricks,
+            tricks_args,
+        }) => run_tricks_command(exec_path, tricks, tricks_args),
+        Some(Commands::ListTricks {
+            exec_path,
+            category,
+            installed_only,
+        }) => list_tricks(exec_path, category, installed_only),
+        Some(Commands::Relink {
+            old_exec_path,
+            new_exec_path,
+            move_prefix,
+        }) => relink(old_exec_path, new_exec_path, move_prefix),
+        Some(Commands::ListPrefixesFor {
+            dir,
+            max_depth,
+            json,
+        }) => list_prefixes_for(dir, max_depth, json),
+        Some(Commands::RepairLinks { auto }) => repair_links(auto),
+        Some(Commands::ShellEnv { exec_path }) => shell_env(exec_path),
+        Some(Commands::Shell { exec_path }) => shell(exec_path),
+        Some(Commands::Probe { exec_path, json }) => probe(exec_path, json),
+        Some(Commands::Snapshot { exec_path, name }) => snapshot(exec_path, name),
+        Some(Commands::Rollback { exec_path, name }) => rollback(exec_path, name),
+        Some(Commands::Stop { exec_path }) => stop(exec_path),
+        Some(Commands::Verify { exec_path }) => verify(exec_path),
+        Some(Commands::ListOrphanDirs { json }) => list_orphan_dirs(json),
+        Some(Commands::DataDir) => print_data_dir(),
+        Some(Commands::Status { json }) => status(json),
+        Some(Commands::PruneTricks {
+            exec_path,
+            tricks,
+            all,
+        }) => prune_tricks(exec_path, tricks, all),
+        None => match cli.run.metrics_file.clone() {
+            Some(metrics_file) => run_with_metrics(cli.run, metrics_file),
+            None => run(cli.run),
+        },
+    }
+}
+/// Metrics of a run which are written to --metrics-file.
+#[derive(Serialize, Debug, Default)]
+struct RunMetrics {
+    duration_secs: f64,
+    winetricks_secs: f64,
+    wine_secs: f64,
+    verbs_installed: usize,
+    prefix_size_before: Option<u64>,
+    prefix_size_after: Option<u64>,
+    exit_code: u8,
+    #[serde(skip)]
+    exec_env_path: Option<PathBuf>,
+}
+/// Update the metrics of the current run when they are collected.
+fn with_metrics(update: impl FnOnce(&mut RunMetrics)) {
+    if let Some(metrics) = RUN_METRICS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_mut()
+    {
+        update(metrics);
+    }
+}
+fn run_with_metrics(args: Args, metrics_file: PathBuf) -> Result<()> {
+    // wine can be run in another current directory.
+    let metrics_file = path::absolute(metrics_file)?;
+    let started = Instant::now();
+    *RUN_METRICS.lock().unwrap_or_else(|e| e.into_inner()) = Some(RunMetrics::default());
+    let result = run(args);
+    let mut metrics = RUN_METRICS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take()
+        .unwrap_or_default();
+    metrics.duration_secs = started.elapsed().as_secs_f64();
+    metrics.exit_code = result.as_ref().err().map_or(0, error_exit_code);
+    // a new prefix which is removed on failure has no size.
+    metrics.prefix_size_after = metrics
+        .exec_env_path
+        .as_ref()
+        .and_then(|exec_env_path| dir_size(exec_env_path).ok());
+    if let Err(e) = fs::write(&metrics_file, serde_json::to_string_pretty(&metrics)?) {
+        warn(format!(
+            "Can not write metrics to {}: {e}",
+            metrics_file.display()
+        ));
+    }
+    result
+}
+
+fn run(mut args: Args) -> Result<()> {
+    let (data_dir, conf) = prepare()?;
+    let source_path = if let Some(url) = &args.download {
+        // --show-config shows the path where exe is downloaded to without downloading it.
+        if args.show_config {
+            get_download_path(url, &data_dir)?
+        } else {
+            download_exe(url, &data_dir, args.sha256.as_deref())?
+        }
+    } else {
+        args.exec_path
+            .take()
+            .ok_or_else(|| anyhow!("exe is not specified."))?
+    };
+    let source_path = if args.replace_path_sep {
+        replace_path_sep(source_path)
+    } else {
+        source_path
+    };
+    if let Some(args_file) = &args.args_file {
+        args.args.splice(0..0, load_args_file(args_file)?);
+    }
+    // a directory of setup files is keyed by the directory and runs the installer in it.
+    let installer = if source_path.is_dir() {
+        Some(find_installer(&source_path)?)
+    } else {
+        if !(args.show_config && args.download.is_some()) {
+            check_exec_path(&source_path)?;
+        }
+        None
+    };
+    let shell_link = if source_path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("lnk"))
+    {
+        Some(parse_shell_link(&source_path)?)
+    } else {
+        None
+    };
+    if let Some(sha256) = &args.sha256
+        && args.download.is_none()
+    {
+        verify_sha256(installer.as_deref().unwrap_or(&source_path), sha256)?;
+    }
+    let file_envs = if let Some(env_file) = &args.env_file {
+        load_env_file(env_file)?
+    } else {
+        vec![]
+    };
+    for category in &args.tricks_category {
+        args.with_tricks
+            .extend(get_category_tricks(category, &data_dir)?);
+    }
+    if args.tricks_args.strict_tricks {
+        check_tricks(&args.with_tricks, &data_dir)?;
+    }
+    let prefixes_dir = get_prefixes_dir(&data_dir, &conf)?;
+    if args.verbose_resolution {
+        explain_exec_env_resolution(&args, &source_path, &data_dir, &prefixes_dir)?;
+    }
+    if args.show_config {
+        return show_config(
+            &args,
+            &source_path,
+            installer.as_deref(),
+            &data_dir,
+            &prefixes_dir,
+            &conf,
+            file_envs,
+        );
+    }
+    let _xvfb = if args.tricks_args.headless {
+        Some(start_xvfb()?)
+    } else {
+        None
+    };
+    let exec_env_conf_path =
+        resolve_args_exec_env_path(&args, &source_path, &data_dir, &prefixes_dir)?
+            .join("conf.toml");
+    if args.abort_on_existing_prefix && exec_env_conf_path.exists() {
+        bail!("prefix is already created for {}", source_path.display());
+    }
+    if args.require_existing_prefix && !exec_env_conf_path.exists() {
+        bail!("prefix is not created yet for {}", source_path.display());
+    }
+    if !args.use_default_prefix && !exec_env_conf_path.exists() {
+        check_free_space(&prefixes_dir, &conf, args.strict_free_space)?;
+    }
+    // a 16-bit exe is checked before the prefix is set up. The target of a shortcut is
+    // checked after the prefix is loaded since it is resolved in the prefix.
+    if shell_link.is_none()
+        && read_exe_kind(installer.as_deref().unwrap_or(&source_path))? == ExeKind::Ne
+    {
+        let mut exec_conf = if exec_env_conf_path.exists() {
+            parse_toml(&fs::read(&exec_env_conf_path)?, &exec_env_conf_path)?
+        } else {
+            ExecEnv::default()
+        };
+        if let Some(proton) = &args.proton {
+            exec_conf.runner = PrefixRunner::Proton(Proton {
+                script: resolve_proton(proton)?,
+            });
+        } else if args.runner == Some(RunnerKind::Wine) {
+            exec_conf.runner = PrefixRunner::default();
+        }
+        check_wine_16bit(&exec_conf)?;
+    }
+    // a dir which exists before the run is never removed even if it has no conf.toml.
+    let exec_env_existed = args.use_default_prefix
+        || fs::symlink_metadata(resolve_exec_env_path(
+            &source_path,
+            &data_dir,
+            &prefixes_dir,
+        )?)
+        .is_ok();
+    let named_created = match &args.prefix_name {
+        Some(prefix_name) => {
+            use_named_exec_env(&source_path, prefix_name, &data_dir, &prefixes_dir)?
+        }
+        None => false,
+    };
+    let (exec_env_path, mut exec_conf, created) = if args.use_default_prefix {
+        load_default_exec_env(&data_dir)?
+    } else {
+        load_exec_env(&source_path, &data_dir, &prefixes_dir)?
+    };
+    let mut new_prefix = NewPrefixGuard {
+        paths: vec![],
+        keep: args.on_failure_keep,
+    };
+    if named_created {
+        new_prefix.paths.push(
+            data_dir
+                .join("named")
+                .join(args.prefix_name.as_deref().unwrap_or_default()),
+        );
+    }
+    let created = created || named_created;
+    with_metrics(|metrics| {
+        metrics.prefix_size_before = if created {
+            Some(0)
+        } else {
+            dir_size(&exec_env_path).ok()
+        };
+        metrics.exec_env_path = Some(exec_env_path.clone());
+    });
+    if created && !exec_env_existed {
+        new_prefix.paths.push(exec_env_path.clone());
+    }
+    if created && !args.quiet {
+        println!(
+            "Create new prefix {}. It can take several GB of disk space.",
+            exec_env_path.display()
+        );
+    }
+    if args.tricks_args.clean_env {
+        set_env_passthrough(args.tricks_args.env_passthrough.clone());
+    }
+    if args.tricks_args.trace_commands {
+        set_command_ledger(
+            get_output_dir(&exec_env_path, &conf, &args.tricks_args)?.join("commands.log"),
+        );
+    }
+    let exec_env_conf_path = exec_env_path.join("conf.toml");
+    let (mut changed, install_runtimes) = apply_exec_env_args(&args, &mut exec_conf, created)?;
+    if let Some(constraint) = &args.wine_version_require {
+        check_wine_version(constraint, &exec_env_path, &exec_conf, &conf)?;
+    }
+    if created {
+        changed |= init_prefix_arch(
+            &mut exec_conf,
+            args.arch,
+            installer.as_deref().unwrap_or(&source_path),
+            &conf,
+        )?;
+    } else if let Some(arch) = args.arch
+        && exec_conf.arch != Some(arch)
+    {
+        // the prefix is recreated with the new architecture.
+        if args.reset_prefix {
+            exec_conf.arch = Some(arch);
+            changed = true;
+        } else {
+            bail!(
+                "prefix is already created as {}. Use --reset-prefix to recreate it as {}.",
+                exec_conf
+                    .arch
+                    .map_or("the default of wine", |arch| arch.as_str()),
+                arch.as_str()
+            );
+        }
+    }
+    if changed {
+        save_exec_env(&exec_env_conf_path, &exec_conf)?;
+    }
+    if let Some(user) = &args.reexec_as
+        && exec_conf.run_as.as_ref() != Some(user)
+    {
+        give_wine_prefix(&exec_env_path, user)?;
+        exec_conf.run_as = Some(user.clone());
+        save_exec_env(&exec_env_conf_path, &exec_conf)?;
+    }
+    if let Some(user) = &exec_conf.run_as {
+        set_run_as(user.clone());
+    }
+    // the runtimes are installed into the recreated prefix.
+    if args.reset_prefix {
+        reset_prefix(&exec_env_path, &mut exec_conf, &conf, &args.tricks_args)?;
+    }
+    if install_runtimes {
+        println!("Install wine runtimes...");
+        let status = exec_command(
+            CommandPurpose::Wine,
+            exec_conf.runner.get().wine_command(),
+            ["wineboot", "-u"],
+            get_wine_prefix(&exec_env_path, &exec_conf),
+            &get_base_envs(&conf, &exec_conf),
+        )?;
+        if !status.success() {
+            return Err(fail(
+                Failure::Wine(status.code()),
+                format!("wineboot is not succeed {status}"),
+            ));
+        }
+    }
+    set_win_envs(&exec_env_path, &mut exec_conf, &conf, &args.win_env)?;
+    let executed_count = exec_conf.executed_tricks.len();
+    run_tricks(
+        &args.with_tricks,
+        &exec_env_path,
+        &mut exec_conf,
+        &conf,
+        &args.tricks_args,
+    )?;
+    with_metrics(|metrics| {
+        metrics.verbs_installed = exec_conf
+            .executed_tricks
+            .len()
+            .saturating_sub(executed_count);
+    });
+    if update_wineprefix_symlink(
+        &exec_env_path,
+        &mut exec_conf,
+        args.wineprefix_symlink.as_deref(),
+    )? && created
+    {
+        new_prefix
+            .paths
+            .extend(exec_conf.wineprefix_symlink.clone());
+    }
+    if args.install_only {
+        println!("Install only, skip running wine.");
+        new_prefix.finish();
+        if created && !args.quiet {
+            print_prefix_size(&exec_env_path)?;
+        }
+        return Ok(());
+    }
+    let mut current_dir = installer.is_some().then(|| source_path.clone());
+    // a shortcut runs its target in the prefix of the shortcut.
+    let exec_path = if let Some(shell_link) = &shell_link {
+        let wine_prefix = get_wine_prefix(&exec_env_path, &exec_conf);
+        if let Some(working_dir) = &shell_link.working_dir {
+            let working_dir = from_windows_path(working_dir, &wine_prefix);
+            if working_dir.is_dir() {
+                current_dir = Some(working_dir);
+            }
+        }
+        if let Some(arguments) = &shell_link.arguments {
+            args.args.splice(0..0, split_command_line(arguments));
+        }
+        resolve_shell_link_target(shell_link, &source_path, &wine_prefix)?
+    } else {
+        installer.clone().unwrap_or_else(|| source_path.clone())
+    };
+    let exec_path = if args.copy_exe_into_prefix {
+        let copied_exe = get_wine_prefix(&exec_env_path, &exec_conf)
+            .join(COPIED_EXE_DIR)
+            .join(
+                exec_path
+                    .file_name()
+                    .ok_or_else(|| anyhow!("Can not get file name."))?,
+            );
+        copy_exe_into_prefix(&exec_path, &copied_exe)?;
+        exec_conf.copied_exe = Some(copied_exe.clone());
+        save_exec_env(&exec_env_conf_path, &exec_conf)?;
+        copied_exe
+    } else {
+        exec_path
+    };
+    if shell_link.is_some() && read_exe_kind(&exec_path)? == ExeKind::Ne {
+        check_wine_16bit(&exec_conf)?;
+    }
+    // the prefix is set up, and other processes can use it while wine runs.
+    new_prefix.finish();
+    unlock_exec_env();
+    let launch = build_launch(
+        &args,
+        &exec_path,
+        current_dir.as_deref(),
+        &exec_env_path,
+        &exec_conf,
+        &conf,
+        file_envs.clone(),
+    )?;
+    println!(
+        "Run {} {}",
+        exec_conf.runner.get().kind().as_str(),
+        exec_path.display()
+    );
+    if let Some(current_dir) = &launch.current_dir {
+        env::set_current_dir(current_dir)?;
+    }
+    if args.sandbox {
+        create_wineserver_dir()?;
+    }
+    if args.wineserver_persist {
+        // wineserver fails when it is already running, which is kept as it is.
+        exec_command(
+            CommandPurpose::Other,
+            exec_conf.runner.get().wineserver_command(),
+            ["-p"],
+            &launch.wine_prefix,
+            &launch.envs,
+        )?;
+    }
+    let screenshot = if args.capture_screenshot_on_exit {
+        Some(start_screenshot_capture(
+            get_output_dir(&exec_env_path, &conf, &args.tricks_args)?.join("screenshot.png"),
+            &launch.envs,
+        )?)
+    } else {
+        None
+    };
+    let status = if args.wine_trace.is_some() {
+        let trace_path =
+            get_output_dir(&exec_env_path, &conf, &args.tricks_args)?.join("wine-trace.log");
+        println!("Write wine trace to {}", trace_path.display());
+        exec_command_with_stderr_file(
+            CommandPurpose::Wine,
+            &launch.program,
+            &launch.args,
+            &launch.wine_prefix,
+            &launch.envs,
+            &trace_path,
+        )?
+    } else {
+        exec_command(
+            CommandPurpose::Wine,
+            &launch.program,
+            &launch.args,
+            &launch.wine_prefix,
+            &launch.envs,
+        )?
+    };
+    if let Some(screenshot) = screenshot {
+        screenshot.finish();
+    }
+    if !status.success() {
+        return Err(fail(
+            Failure::Wine(status.code()),
+            format!("wine is not succeed {status}"),
+        ));
+    }
+    if args.wait_wineserver && !args.wineserver_persist {
+        wait_wineserver(&exec_conf, &launch.wine_prefix, &launch.envs);
+    }
+    if let Some(after_install_run) = &args.after_install_run {
+        let after_exec_path = from_windows_path(after_install_run, &launch.wine_prefix);
+        if !after_exec_path.is_file() {
+            bail!("Can not find {} after install.", after_exec_path.display());
+        }
+        // the arguments are for the installer.
+        let after_args = Args {
+            args: vec![],
+            ..args.clone()
+        };
+        let after_launch = build_launch(
+            &after_args,
+            &after_exec_path,
+            after_exec_path.parent(),
+            &exec_env_path,
+            &exec_conf,
+            &conf,
+            file_envs,
+        )?;
+        println!("Run {} after install", after_exec_path.display());
+        if let Some(current_dir) = &after_launch.current_dir {
+            env::set_current_dir(current_dir)?;
+        }
+        let status = exec_command(
+            CommandPurpose::Wine,
+            &after_launch.program,
+            &after_launch.args,
+            &after_launch.wine_prefix,
+            &after_launch.envs,
+        )?;
+        if !status.success() {
+            return Err(fail(
+                Failure::Wine(status.code()),
+                format!("wine is not succeed {status}"),
+            ));
+        }
+        if args.wait_wineserver && !args.wineserver_persist {
+            wait_wineserver(&exec_conf, &after_launch.wine_prefix, &after_launch.envs);
+        }
+    }
+    if created && !args.quiet {
+        print_prefix_size(&exec_env_path)?;
+    }
+
+    Ok(())
+}
+/// Apply the per-prefix options of `args` to `exec_conf`.
+/// Returns whether `exec_conf` is changed and whether wine runtimes should be installed.
+/// `created` tells that the prefix is new and its runner can be changed without a reset.
+fn apply_exec_env_args(
+    args: &Args,
+    exec_conf: &mut ExecEnv,
+    created: bool,
+) -> Result<(bool, bool)> {
+    let mut changed = false;
+    let kind = exec_conf.runner.get().kind();
+    match args.runner {
+        Some(RunnerKind::Wine) if args.proton.is_some() => {
+            bail!("--proton can not be used with --runner wine.");
+        }
+        Some(RunnerKind::Wine) if kind == RunnerKind::Proton => {
+            // the wine prefix of proton is in another place.
+            if !args.reset_prefix {
+                bail!("prefix is created for proton. Use --reset-prefix to recreate it for wine.");
+            }
+            exec_conf.runner = PrefixRunner::default();
+            changed = true;
+        }
+        Some(RunnerKind::Proton) if args.proton.is_none() && kind != RunnerKind::Proton => {
+            bail!("--runner proton needs --proton.");
+        }
+        _ => {}
+    }
+    if let Some(proton) = &args.proton {
+        // the wine prefix of proton is in another place, where executed tricks are not installed.
+        if kind == RunnerKind::Wine && !created && !args.reset_prefix {
+            bail!("prefix is created for wine. Use --reset-prefix to recreate it for proton.");
+        }
+        exec_conf.runner = PrefixRunner::Proton(Proton {
+            script: resolve_proton(proton)?,
+        });
+        changed = true;
+    }
+    if args.disable_esync && !exec_conf.disable_esync {
+        exec_conf.disable_esync = true;
+        changed = true;
+    }
+    if let Some(lang) = &args.lang
+        && exec_conf.lang.as_ref() != Some(lang)
+    {
+        warn_unavailable_locale(lang);
+        exec_conf.lang = Some(lang.clone());
+        changed = true;
+    }
+    let mut install_runtimes = false;
+    for (no_runtime, install_runtime, runtime) in [
+        (args.no_mono, args.install_mono, &mut exec_conf.mono),
+        (args.no_gecko, args.install_gecko, &mut exec_conf.gecko),
+    ] {
+        if no_runtime && *runtime != Some(false) {
+            *runtime = Some(false);
+            changed = true;
+        } else if install_runtime && *runtime != Some(true) {
+            *runtime = Some(true);
+            install_runtimes = true;
+            changed = true;
+        }
+    }
+    if !args.dll_override.is_empty() {
+        exec_conf.dll_overrides.extend(args.dll_override.clone());
+        changed = true;
+    }
+    for winepath in &args.winepath {
+        let winepath = if is_windows_path(winepath) {
+            winepath.clone()
+        } else {
+            path::absolute(winepath)?.to_string_lossy().to_string()
+        };
+        if !exec_conf.winepath.contains(&winepath) {
+            exec_conf.winepath.push(winepath);
+            changed = true;
+        }
+    }
+    for winedllpath in &args.winedllpath {
+        if !winedllpath.is_dir() {
+            bail!("dll directory is not found: {}", winedllpath.display());
+        }
+        let winedllpath = path::absolute(winedllpath)?;
+        if !exec_conf.winedllpath.contains(&winedllpath) {
+            exec_conf.winedllpath.push(winedllpath);
+            changed = true;
+        }
+    }
+    Ok((changed, install_runtimes))
+}
+/// Command which launches exe.
+#[derive(Serialize, Debug)]
+struct Launch {
+    program: String,
+    args: Vec<String>,
+    wine_prefix: PathBuf,
+    envs: BTreeMap<String, String>,
+    current_dir: Option<PathBuf>,
+}
+fn build_launch(
+    args: &Args,
+    exec_path: impl AsRef<Path>,
+    current_dir: Option<&Path>,
+    exec_env_path: impl AsRef<Path>,
+    exec_conf: &ExecEnv,
+    conf: &Config,
+    file_envs: Vec<(String, String)>,
+) -> Result<Launch> {
+    let exec_path = exec_path.as_ref();
+    let exec_env_path = exec_env_path.as_ref();
+    let exec_env_wine_path = exec_env_path.join(".wine");
+    let wine_prefix = get_wine_prefix(exec_env_path, exec_conf);
+    let exec_path_str = exec_path.to_string_lossy().to_string();
+    let mut wine_args = build_wine_args(&exec_path_str, &args.args);
+    let exe_args_len = wine_args.len();
+    if let Some(win_priority) = args.win_priority {
+        let mut start_args = vec![
+            "start".to_string(),
+            "/wait".to_string(),
+            win_priority.start_option().to_string(),
+        ];
+        if wine_args.first() == Some(&exec_path_str) {
+            start_args.push("/unix".to_string());
+        }
+        wine_args.splice(0..0, start_args);
+    }
+    let mut envs = get_prefix_envs(conf, exec_conf, &wine_prefix)?;
+    if let Some(gpu) = &args.gpu {
+        envs.extend(get_gpu_envs(gpu));
+    }
+    // the display can not be connected without them, which --clean-env removes.
+    let mut display_envs = vec![];
+    if let Some(display) = &args.display {
+        envs.insert("DISPLAY".to_string(), display.clone());
+        display_envs.push("XAUTHORITY");
+    }
+    if let Some(wayland_display) = &args.wayland_display {
+        envs.insert("WAYLAND_DISPLAY".to_string(), wayland_display.clone());
+        display_envs.push("XDG_RUNTIME_DIR");
+    }
+    if args.tricks_args.clean_env {
+        for key in display_envs {
+            if let Ok(value) = env::var(key) {
+                envs.insert(key.to_string(), value);
+            }
+        }
+    }
+    envs.extend(file_envs);
+    envs.extend(args.env.clone());
+    if let Some(channels) = &args.wine_trace {
+        envs.insert("WINEDEBUG".to_string(), channels.clone());
+    }
+    let (program, program_args) =
+        exec_conf
+            .runner
+            .get()
+            .launch_command(wine_args, &exec_env_wine_path, &mut envs);
+    let (program, program_args) = if conf.wine_command_template.is_empty() {
+        (program, program_args)
+    } else {
+        let wine_command = [program]
+            .into_iter()
+            .chain(
+                program_args[..program_args.len() - exe_args_len]
+                    .iter()
+                    .cloned(),
+            )
+            .collect::<Vec<_>>();
+        let mut command = apply_wine_command_template(
+            &conf.wine_command_template,
+            &wine_command,
+            &exec_path_str,
+            &args.args,
+        )?;
+        let program = command.remove(0);
+        (program, command)
+    };
+    let (program, program_args) = if args.sandbox {
+        let mut sandbox_args =
+            get_sandbox_args(exec_env_path, exec_path, exec_conf, &args.sandbox_bind)?;
+        sandbox_args.push("--".to_string());
+        sandbox_args.push(program);
+        sandbox_args.extend(program_args);
+        ("bwrap".to_string(), sandbox_args)
+    } else {
+        (program, program_args)
+    };
+    Ok(Launch {
+        program,
+        args: program_args,
+        wine_prefix,
+        envs,
+        current_dir: current_dir.map(|dir| dir.to_path_buf()),
+    })
+}
+/// Print the effective configuration and the launch command as JSON without running anything.
+fn show_config(
+    args: &Args,
+    source_path: impl AsRef<Path>,
+    installer: Option<&Path>,
+    data_dir: impl AsRef<Path>,
+    prefixes_dir: impl AsRef<Path>,
+    conf: &Config,
+    file_envs: Vec<(String, String)>,
+) -> Result<()> {
+    let source_path = source_path.as_ref();
+    let exec_env_path = resolve_args_exec_env_path(args, source_path, data_dir, prefixes_dir)?;
+    let exec_env_conf_path = exec_env_path.join("conf.toml");
+    let mut exec_conf = if exec_env_conf_path.exists() {
+        parse_toml(&fs::read(&exec_env_conf_path)?, &exec_env_conf_path)?
+    } else {
+        ExecEnv::default()
+    };
+    if args.use_default_prefix {
+        exec_conf.wine_prefix = Some(get_default_wine_prefix()?);
+    }
+    apply_exec_env_args(args, &mut exec_conf, !exec_env_conf_path.exists())?;
+    let exec_path = installer.unwrap_or(source_path);
+    let exec_path = if args.copy_exe_into_prefix {
+        get_wine_prefix(&exec_env_path, &exec_conf)
+            .join(COPIED_EXE_DIR)
+            .join(
+                exec_path
+                    .file_name()
+                    .ok_or_else(|| anyhow!("Can not get file name."))?,
+            )
+    } else {
+        exec_path.to_path_buf()
+    };
+    let launch = build_launch(
+        args,
+        &exec_path,
+        installer.map(|_| source_path),
+        &exec_env_path,
+        &exec_conf,
+        conf,
+        file_envs,
+    )?;
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&serde_json::json!({
+            "config": conf,
+            "exec_env_dir": exec_env_path,
+            "exec_env": exec_conf,
+            "launch": launch,
+        }))?
+    );
+    Ok(())
+}
+fn run_tricks_command(
+    exec_path: PathBuf,
+    tricks: Vec<String>,
+    tricks_args: TricksArgs,
+) -> Result<()> {
+    // a directory of setup files has the prefix of the directory like run.
+    let installer = if exec_path.is_dir() {
+        Some(find_installer(&exec_path)?)
+    } else {
+        check_exec_path(&exec_path)?;
+        None
+    };
+    let (data_dir, conf) = prepare()?;
+    if tricks_args.strict_tricks {
+        check_tricks(&tricks, &data_dir)?;
+    }
+    let _xvfb = if tricks_args.headless {
+        Some(start_xvfb()?)
+    } else {
+        None
+    };
+    let prefixes_dir = get_prefixes_dir(&data_dir, &conf)?;
+    if !resolve_exec_env_path(&exec_path, &data_dir, &prefixes_dir)?
+        .join("conf.toml")
+        .exists()
+    {
+        check_free_space(&prefixes_dir, &conf, false)?;
+    }
+    let (exec_env_path, mut exec_conf, created) =
+        load_exec_env(&exec_path, &data_dir, &prefixes_dir)?;
+    if created {
+        println!(
+            "Create new prefix {}. It can take several GB of disk space.",
+            exec_env_path.display()
+        );
+        if init_prefix_arch(
+            &mut exec_conf,
+            None,
+            installer.as_deref().unwrap_or(&exec_path),
+            &conf,
+        )? {
+            save_exec_env(exec_env_path.join("conf.toml"), &exec_conf)?;
+        }
+    }
+    if tricks_args.clean_env {
+        set_env_passthrough(tricks_args.env_passthrough.clone());
+    }
+    if let Some(user) = &exec_conf.run_as {
+        set_run_as(user.clone());
+    }
+    if tricks_args.trace_commands {
+        set_command_ledger(
+            get_output_dir(&exec_env_path, &conf, &tricks_args)?.join("commands.log"),
+        );
+    }
+    run_tricks(&tricks, &exec_env_path, &mut exec_conf, &conf, &tricks_args)
+}
+/// Decide the architecture of a new prefix. Returns whether it is decided.
+/// The order is --arch, win64 for 64-bit exe which can not run in a win32 prefix, and
+/// arch_default of config. 32-bit exe runs in both.
+fn init_prefix_arch(
+    exec_conf: &mut ExecEnv,
+    arch: Option<WineArch>,
+    exec_path: &Path,
+    conf: &Config,
+) -> Result<bool> {
+    // proton and the default prefix decide it by themselves.
+    if exec_conf.runner.get().kind() == RunnerKind::Proton || exec_conf.wine_prefix.is_some() {
+        return Ok(false);
+    }
+    let detected = if exec_path.is_file() && read_exe_kind(exec_path)? == ExeKind::Pe {
+        read_pe_info(exec_path)
+            .ok()
+            .filter(|info| info.bits == 64)
+            .map(|_| WineArch::Win64)
+    } else {
+        None
+    };
+    exec_conf.arch = arch.or(detected).or(conf.arch_default);
+    Ok(exec_conf.arch.is_some())
+}
+/// Environment variables applied to both winetricks and wine.
+fn get_base_envs(conf: &Config, exec_conf: &ExecEnv) -> BTreeMap<String, String> {
+    let mut envs = BTreeMap::new();
+    if let Some(arch) = exec_conf.arch {
+        envs.insert("WINEARCH".to_string(), arch.as_str().to_string());
+    }
+    if conf.disable_esync || exec_conf.disable_esync {
+        envs.insert("WINEESYNC".to_string(), "0".to_string());
+        envs.insert("WINEFSYNC".to_string(), "0".to_string());
+    }
+    if let Some(lang) = &exec_conf.lang {
+        envs.insert("LANG".to_string(), lang.clone());
+        envs.insert("LC_ALL".to_string(), lang.clone());
+    }
+    let dll_overrides = get_runtime_dll_overrides(exec_conf);
+    if !dll_overrides.is_empty() {
+        envs.insert(
+            WINEDLLOVERRIDES.to_string(),
+            dll_overrides_env(&dll_overrides),
+        );
+    }
+    envs
+}
+/// Environment variables saved in the prefix which are applied to exe.
+fn get_prefix_envs(
+    conf: &Config,
+    exec_conf: &ExecEnv,
+    wine_prefix: &Path,
+) -> Result<BTreeMap<String, String>> {
+    let mut envs = get_base_envs(conf, exec_conf);
+    let mut dll_overrides = get_runtime_dll_overrides(exec_conf);
+    dll_overrides.extend(exec_conf.dll_overrides.clone());
+    if !dll_overrides.is_empty() {
+        envs.insert(
+            WINEDLLOVERRIDES.to_string(),
+            dll_overrides_env(&dll_overrides),
+        );
+    }
+    if !exec_conf.winepath.is_empty() {
+        envs.insert(
+            "WINEPATH".to_string(),
+            exec_conf
+                .winepath
+                .iter()
+                .map(|winepath| to_windows_path(winepath, wine_prefix))
+                .collect::<Vec<_>>()
+                .join(";"),
+        );
+    }
+    if !exec_conf.winedllpath.is_empty() {
+        envs.insert(
+            "WINEDLLPATH".to_string(),
+            env::join_paths(&exec_conf.winedllpath)?
+                .to_string_lossy()
+                .to_string(),
+        );
+    }
+    Ok(envs)
+}
+/// Dll overrides which suppress installing wine mono and gecko.
+/// They are applied to winetricks too because any first wine invocation initializes the prefix.
+fn get_runtime_dll_overrides(exec_conf: &ExecEnv) -> BTreeMap<String, String> {
+    let mut dll_overrides = BTreeMap::new();
+    if exec_conf.mono == Some(false) {
+        dll_overrides.insert("mscoree".to_string(), String::new());
+    }
+    if exec_conf.gecko == Some(false) {
+        dll_overrides.insert("mshtml".to_string(), String::new());
+    }
+    dll_overrides
+}
+fn parse_lang(s: &str) -> Result<String, String> {
+    let language = s.split(['_', '.', '@']).next().unwrap_or_default();
+    if s == "C"
+        || s == "POSIX"
+        || s.starts_with("C.")
+        || ((2..=3).contains(&language.len())
+            && language.chars().all(|c| c.is_ascii_lowercase())
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_.@-".contains(c)))
+    {
+        Ok(s.to_string())
+    } else {
+        Err(format!("invalid locale: {s}"))
+    }
+}
+fn warn_unavailable_locale(lang: &str) {
+    let normalize = |locale: &str| locale.to_lowercase().replace('-', "");
+    if let Ok(output) = Command::new("locale").arg("-a").output()
+        && output.status.success()
+        && !String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .any(|locale| normalize(locale) == normalize(lang))
+    {
+        warn(format!("locale {lang} is not available on this system."));
+    }
+}
+fn esync_hint(stderr_tail: &[String]) -> &'static str {
+    if stderr_tail
+        .iter()
+        .any(|line| FD_LIMIT_ERRORS.iter().any(|e| line.contains(e)))
+    {
+        "\nFile descriptor limit seems to be too low for esync. Try --disable-esync."
+    } else {
+        ""
+    }
+}
+fn build_wine_args(exec_path_str: &str, args: &[String]) -> Vec<String> {
+    if args.iter().any(|arg| arg.contains(EXE_PLACEHOLDER)) {
+        args.iter()
+            .map(|arg| arg.replace(EXE_PLACEHOLDER, exec_path_str))
+            .collect()
+    } else {
+        let mut wine_args = vec![exec_path_str.to_string()];
+        wine_args.extend_from_slice(args);
+        wine_args
+    }
+}
+/// Expand `wine_command_template` of config.
+/// `{wine}` is the wine command with its own arguments, such as `proton run`.
+fn apply_wine_command_template(
+    template: &[String],
+    wine_command: &[String],
+    exec_path_str: &str,
+    args: &[String],
+) -> Result<Vec<String>> {
+    if !template.iter().any(|arg| arg == WINE_PLACEHOLDER)
+        || !template.iter().any(|arg| arg.contains(EXE_PLACEHOLDER))
+    {
+        return Err(fail(
+            Failure::Config,
+            format!("wine_command_template must contain {WINE_PLACEHOLDER} and {EXE_PLACEHOLDER}."),
+        ));
+    }
+    let mut command = vec![];
+    for arg in template {
+        match arg.as_str() {
+            WINE_PLACEHOLDER => command.extend_from_slice(wine_command),
+            ARGS_PLACEHOLDER => command.extend(
+                args.iter()
+                    .map(|arg| arg.replace(EXE_PLACEHOLDER, exec_path_str)),
+            ),
+            _ => command.push(arg.replace(EXE_PLACEHOLDER, exec_path_str)),
+        }
+    }
+    Ok(command)
+}
+fn list_tricks(exec_path: PathBuf, category: Option<String>, installed_only: bool) -> Result<()> {
+    let (data_dir, conf) = prepare()?;
+    let prefixes_dir = get_prefixes_dir(&data_dir, &conf)?;
+    let exec_env_path = resolve_exec_env_path(&exec_path, &data_dir, &prefixes_dir)?;
+    // a prefix which is not created yet has nothing executed nor installed.
+    let (exec_conf, installed_tricks) = if exec_env_path.join("conf.toml").exists() {
+        let (exec_env_path, exec_conf) =
+            load_existing_exec_env(&exec_path, &data_dir, &prefixes_dir)?;
+        let installed_tricks = get_installed_tricks(&conf, &exec_env_path, &exec_conf)?;
+        (exec_conf, installed_tricks)
+    } else {
+        (ExecEnv::default(), HashSet::new())
+    };
+    let mut current_category = None;
+    for (verb_category, verb, description) in
+        parse_winetricks_list(&get_winetricks_list(&data_dir)?)
+    {
+        if category
+            .as_ref()
+            .is_some_and(|category| *category != verb_category)
+        {
+            continue;
+        }
+        let status = if exec_conf.executed_tricks.contains(&verb) {
+            "executed"
+        } else if installed_tricks.contains(&verb) {
+            "installed"
+        } else if installed_only {
+            continue;
+        } else {
+            ""
+        };
+        if current_category.as_ref() != Some(&verb_category) {
+            println!("===== {verb_category} =====");
+            current_category = Some(verb_category);
+        }
+        match exec_conf.trick_durations.get(&verb) {
+            Some(duration) => println!("{status:<9} {verb:<24} {description} ({duration:.1}s)"),
+            None => println!("{status:<9} {verb:<24} {description}"),
+        }
+    }
+    Ok(())
+}
+/// Verbs which winetricks list-installed reports for the prefix.
+fn get_installed_tricks(
+    conf: &Config,
+    exec_env_path: impl AsRef<Path>,
+    exec_conf: &ExecEnv,
+) -> Result<HashSet<String>> {
+    let mut tricks_envs = get_base_envs(conf, exec_conf);
+    tricks_envs.extend(exec_conf.runner.get().tricks_envs());
+    let command = OsStr::new("winetricks");
+    let output = new_command(
+        command,
+        ["list-installed"],
+        get_wine_prefix(exec_env_path, exec_conf),
+        &tricks_envs,
+    )
+    .stdout(Stdio::piped())
+    .stderr(Stdio::null())
+    .output()
+    .map_err(|e| spawn_error(command, e))?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .map(|trick| trick.to_string())
+        .collect())
+}
+/// Link the exec env of new exe to the exec env of old exe, or move it with `move_prefix`.
+fn relink(old_exec_path: PathBuf, new_exec_path: PathBuf, move_prefix: bool) -> Result<()> {
+    let (data_dir, conf) = prepare()?;
+    let prefixes_dir = get_prefixes_dir(&data_dir, &conf)?;
+    let (old_exec_env_path, _) = load_existing_exec_env(&old_exec_path, &data_dir, &prefixes_dir)?;
+    let new_exec_env_path = resolve_exec_env_path(&new_exec_path, &data_dir, &prefixes_dir)?;
+    if fs::symlink_metadata(&new_exec_env_path).is_ok() {
+        bail!(
+            "{} already uses prefix {}",
+            new_exec_path.display(),
+            new_exec_env_path.display()
+        );
+    }
+    if move_prefix {
+        println!(
+            "Move prefix {} to {}",
+            old_exec_env_path.display(),
+            new_exec_env_path.display()
+        );
+        fs::rename(&old_exec_env_path, &new_exec_env_path)?;
+        let (_, mut exec_conf) = load_existing_exec_env(&new_exec_path, &data_dir, &prefixes_dir)?;
+        if let Some(copied_exe) = &exec_conf.copied_exe
+            && let Ok(path) = copied_exe.strip_prefix(&old_exec_env_path)
+        {
+            exec_conf.copied_exe = Some(new_exec_env_path.join(path));
+            save_exec_env(new_exec_env_path.join("conf.toml"), &exec_conf)?;
+        }
+        update_wineprefix_symlink(&new_exec_env_path, &mut exec_conf, None)?;
+    } else {
+        println!(
+            "Link prefix {} to {}",
+            new_exec_env_path.display(),
+            old_exec_env_path.display()
+        );
+        link_exec_env(&old_exec_env_path, &new_exec_env_path)?;
+    }
+    Ok(())
+}
+/// Point the symlink of --wineprefix-symlink to the wine prefix, such as after the prefix is
+/// moved. `new_link` replaces the recorded symlink. Only the recorded symlink is replaced, and
+/// other files at the path are kept. Returns whether the symlink is made.
+fn update_wineprefix_symlink(
+    exec_env_path: impl AsRef<Path>,
+    exec_conf: &mut ExecEnv,
+    new_link: Option<&Path>,
+) -> Result<bool> {
+    let exec_env_path = exec_env_path.as_ref();
+    let Some(link) = new_link
+        .map(path::absolute)
+        .transpose()?
+        .or_else(|| exec_conf.wineprefix_symlink.clone())
+    else {
+        return Ok(false);
+    };
+    let is_recorded = exec_conf.wineprefix_symlink.as_ref() == Some(&link);
+    let wine_prefix = path::absolute(get_wine_prefix(exec_env_path, exec_conf))?;
+    let is_linked = match fs::read_link(&link) {
+        Ok(target) if target == wine_prefix => true,
+        Ok(_) if is_recorded => {
+            fs::remove_file(&link)?;
+            false
+        }
+        Err(_) if fs::symlink_metadata(&link).is_err() => false,
+        _ => bail!(
+            "Can not make symlink {} since it already exists.",
+            link.display()
+        ),
+    };
+    if !is_recorded {
+        // the old symlink is replaced by the new one.
+        if let Some(old_link) = exec_conf.wineprefix_symlink.replace(link.clone())
+            && fs::symlink_metadata(&old_link).is_ok_and(|metadata| metadata.is_symlink())
+        {
+            fs::remove_file(&old_link)?;
+        }
+        save_exec_env(exec_env_path.join("conf.toml"), exec_conf)?;
+    }
+    if is_linked {
+        return Ok(false);
+    }
+    println!("Link {} to {}", link.display(), wine_prefix.display());
+    std::os::unix::fs::symlink(&wine_prefix, &link)
+        .map_err(|e| anyhow!(e).context(format!("Can not make symlink {}.", link.display())))?;
+    Ok(true)
+}
+/// Make `link` a symlink to the exec env `target`.
+/// The link is relative when `target` is under the directory of `link`, so that it keeps working
+/// when the directory is moved.
+fn link_exec_env(target: impl AsRef<Path>, link: impl AsRef<Path>) -> Result<()> {
+    let target = path::absolute(target)?;
+    let link = path::absolute(link)?;
+    let target = match link.parent().and_then(|dir| target.strip_prefix(dir).ok()) {
+        Some(relative_target) => relative_target.to_path_buf(),
+        None => target,
+    };
+    std::os::unix::fs::symlink(target, link)?;
+    Ok(())
+}
+/// Use the named exec env for exe. The exec env of exe becomes a link to it.
+/// Returns whether the named exec env is newly created.
+fn use_named_exec_env(
+    exec_path: impl AsRef<Path>,
+    prefix_name: &str,
+    data_dir: impl AsRef<Path>,
+    prefixes_dir: impl AsRef<Path>,
+) -> Result<bool> {
+    let exec_path = exec_path.as_ref();
+    let named_exec_env_path = path::absolute(data_dir.as_ref().join("named").join(prefix_name))?;
+    let exec_env_path = resolve_exec_env_path(exec_path, &data_dir, prefixes_dir)?;
+    if fs::symlink_metadata(&exec_env_path).is_ok() {
+        if fs::canonicalize(&exec_env_path).ok() == fs::canonicalize(&named_exec_env_path).ok() {
+            return Ok(false);
+        }
+        bail!(
+            "{} already uses prefix {}",
+            exec_path.display(),
+            exec_env_path.display()
+        );
+    }
+    let created = !named_exec_env_path.exists() && create_exec_env_dir(&named_exec_env_path)?;
+    println!(
+        "Link prefix {} to {}",
+        exec_env_path.display(),
+        named_exec_env_path.display()
+    );
+    link_exec_env(&named_exec_env_path, &exec_env_path)?;
+    Ok(created)
+}
+fn parse_display(s: &str) -> Result<String, String> {
+    match s.rsplit_once(':') {
+        Some((_, number))
+            if !number.is_empty() && number.chars().all(|c| c.is_ascii_digit() || c == '.') =>
+        {
+            Ok(s.to_string())
+        }
+        _ => Err(format!("invalid X display: {s}")),
+    }
+}
+/// Parse constraints such as ">=9.0,<10". A version without an operator means >=.
+fn parse_version_constraint(s: &str) -> Result<VersionConstraint, String> {
+    let requirements = s
+        .split(',')
+        .map(|requirement| {
+            let requirement = requirement.trim();
+            let op = [">=", "<=", "==", ">", "<", "="]
+                .into_iter()
+                .find(|op| requirement.starts_with(op))
+                .unwrap_or("");
+            let version = WineVersion::parse(&requirement[op.len()..])
+                .ok_or_else(|| format!("invalid version constraint: {requirement}"))?;
+            let op = if op.is_empty() { ">=" } else { op };
+            Ok((op.to_string(), version))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok(VersionConstraint {
+        text: s.to_string(),
+        requirements,
+    })
+}
+fn parse_prefix_name(s: &str) -> Result<String, String> {
+    if s.is_empty() || s == "." || s == ".." || s.contains('/') {
+        Err(format!("invalid prefix name: {s}"))
+    } else {
+        Ok(s.to_string())
+    }
+}
+fn find_exe_files(dir: &Path, depth: usize, max_depth: Option<usize>) -> Result<Vec<PathBuf>> {
+    let mut exe_files = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        // symlinked directories are not followed to avoid loops.
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            if max_depth.is_none_or(|max_depth| depth < max_depth) {
+                exe_files.extend(find_exe_files(&path, depth + 1, max_depth)?);
+            }
+        } else if path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("exe"))
+            && path.is_file()
+        {
+            exe_files.push(fs::canonicalize(&path)?);
+        }
+    }
+    Ok(exe_files)
+}
+/// Print exe files under `dir` with their prefixes.
+fn list_prefixes_for(dir: PathBuf, max_depth: Option<usize>, json: bool) -> Result<()> {
+    let (data_dir, conf) = prepare()?;
+    let prefixes_dir = get_prefixes_dir(&data_dir, &conf)?;
+    let mut exe_files = find_exe_files(&dir, 0, max_depth)?;
+    exe_files.sort();
+    let mut results = vec![];
+    for exec_path in exe_files {
+        let exec_env_path = resolve_exec_env_path(&exec_path, &data_dir, &prefixes_dir)?;
+        let exec_env_path = exec_env_path
+            .join("conf.toml")
+            .exists()
+            .then(|| fs::canonicalize(&exec_env_path).unwrap_or(exec_env_path));
+        results.push((exec_path, exec_env_path));
+    }
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(
+                &results
+                    .iter()
+                    .map(|(exec_path, exec_env_path)| serde_json::json!({
+                        "exe": exec_path,
+                        "prefix": exec_env_path,
+                    }))
+                    .collect::<Vec<_>>()
+            )?
+        );
+        return Ok(());
+    }
+    for (exec_path, exec_env_path) in results {
+        match exec_env_path {
+            Some(exec_env_path) => println!(
+                "managed   {} -> {}",
+                exec_path.display(),
+                exec_env_path.display()
+            ),
+            None => println!("unmanaged {}", exec_path.display()),
+        }
+    }
+    Ok(())
+}
+/// Relink the links of prefixes_dir whose targets are missing to a prefix of the same name in
+/// prefixes_dir or the named prefixes, such as after moving the data dir, or remove them.
+fn repair_links(auto: bool) -> Result<()> {
+    let (data_dir, conf) = prepare()?;
+    let prefixes_dir = get_prefixes_dir(&data_dir, &conf)?;
+    let mut links = vec![];
+    for entry in fs::read_dir(&prefixes_dir)? {
+        let path = entry?.path();
+        if fs::symlink_metadata(&path)?.is_symlink() && !path.join("conf.toml").exists() {
+            links.push(path);
+        }
+    }
+    links.sort();
+    let mut fixed = 0;
+    for link in &links {
+        let target = fs::read_link(link)?;
+        println!("{} -> {} is missing", link.display(), target.display());
+        let candidate = target.file_name().and_then(|name| {
+            [prefixes_dir.join(name), data_dir.join("named").join(name)]
+                .into_iter()
+                .find(|candidate| candidate != link && candidate.join("conf.toml").exists())
+        });
+        match candidate {
+            Some(candidate) => {
+                if auto || confirm_destructive(&format!("Link to {}?", candidate.display()))? {
+                    fs::remove_file(link)?;
+                    link_exec_env(&candidate, link)?;
+                    println!("  linked to {}", candidate.display());
+                    fixed += 1;
+                }
+            }
+            None => {
+                if auto || confirm_destructive("Remove the link?")? {
+                    fs::remove_file(link)?;
+                    println!("  removed");
+                    fixed += 1;
+                }
+            }
+        }
+    }
+    println!("{} broken links, {fixed} fixed", links.len());
+    Ok(())
+}
+fn print_data_dir() -> Result<()> {
+    let (data_dir, _) = prepare()?;
+    println!("{}", data_dir.display());
+    Ok(())
+}
+/// Print the data dir, the prefixes with their problems and the versions of wine tools.
+fn status(json: bool) -> Result<()> {
+    let (data_dir, conf) = prepare()?;
+    let prefixes_dir = get_prefixes_dir(&data_dir, &conf)?;
+    let mut prefixes = vec![];
+    let mut total_size = 0;
+    let mut entries = fs::read_dir(&prefixes_dir)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>>>()?;
+    let named_dir = data_dir.join("named");
+    if named_dir.is_dir() {
+        for entry in fs::read_dir(&named_dir)? {
+            entries.push(entry?.path());
+        }
+    }
+    entries.sort();
+    for exec_env_path in entries {
+        let is_link = fs::symlink_metadata(&exec_env_path)?.is_symlink();
+        let exec_env_conf_path = exec_env_path.join("conf.toml");
+        let mut problems = vec![];
+        if is_link && !exec_env_path.exists() {
+            problems.push("linked prefix is missing".to_string());
+        } else if !exec_env_conf_path.exists() {
+            // not a prefix, such as downloads.
+            continue;
+        } else {
+            match fs::read(&exec_env_conf_path)
+                .map_err(anyhow::Error::from)
+                .and_then(|data| parse_toml::<ExecEnv>(&data, &exec_env_conf_path))
+            {
+                Ok(exec_conf) => {
+                    if let PrefixRunner::Proton(proton) = &exec_conf.runner
+                        && !proton.script.exists()
+                    {
+                        problems.push(format!("proton is missing: {}", proton.script.display()));
+                    }
+                    if !get_wine_prefix(&exec_env_path, &exec_conf).exists() {
+                        problems.push("wine prefix is missing".to_string());
+                    }
+                }
+                Err(e) => problems.push(e.to_string()),
+            }
+        }
+        // a linked prefix is counted by its target.
+        let size = if is_link {
+            0
+        } else {
+            dir_size(&exec_env_path)?
+        };
+        total_size += size;
+        prefixes.push((exec_env_path, is_link, size, problems));
+    }
+    let tool_version = |command: &str, arg: &str| {
+        Command::new(command)
+            .arg(arg)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .output()
+            .ok()
+            .and_then(|output| {
+                String::from_utf8_lossy(&output.stdout)
+                    .lines()
+                    .next()
+                    .map(|line| line.trim().to_string())
+            })
+    };
+    let tools = [("wine", "--version"), ("winetricks", "--version")]
+        .into_iter()
+        .map(|(command, arg)| (command, tool_version(command, arg)))
+        .collect::<Vec<_>>();
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&serde_json::json!({
+                "data_dir": data_dir,
+                "prefixes_dir": prefixes_dir,
+                "prefixes": prefixes
+                    .iter()
+                    .map(|(path, is_link, size, problems)| serde_json::json!({
+                        "path": path,
+                        "link": is_link,
+                        "size": size,
+                        "problems": problems,
+                    }))
+                    .collect::<Vec<_>>(),
+                "total_size": total_size,
+                "tools": tools.iter().cloned().collect::<BTreeMap<_, _>>(),
+            }))?
+        );
+        return Ok(());
+    }
+    println!("Data dir: {}", data_dir.display());
+    println!("Prefixes dir: {}", prefixes_dir.display());
+    println!("Prefixes: {} ({})", prefixes.len(), format_size(total_size));
+    for (path, is_link, size, problems) in &prefixes {
+        let name = path
+            .strip_prefix(&prefixes_dir)
+            .or_else(|_| path.strip_prefix(&data_dir))
+            .unwrap_or(path)
+            .display();
+        if *is_link {
+            println!("  {name} (link)");
+        } else {
+            println!("  {name} {}", format_size(*size));
+        }
+        for problem in problems {
+            println!("    problem: {problem}");
+        }
+    }
+    for (command, version) in &tools {
+        println!("{command}: {}", version.as_deref().unwrap_or("not found"));
+    }
+    Ok(())
+}
+fn list_orphan_dirs(json: bool) -> Result<()> {
+    let (data_dir, conf) = prepare()?;
+    let prefixes_dir = get_prefixes_dir(&data_dir, &conf)?;
+    let named_dir = data_dir.join("named");
+    let mut orphans = vec![];
+    let mut linked = HashSet::new();
+    let mut exec_env_paths = vec![];
+    for entry in fs::read_dir(&prefixes_dir)? {
+        let path = entry?.path();
+        if fs::symlink_metadata(&path)?.is_symlink() {
+            if let Ok(target) = fs::canonicalize(&path) {
+                linked.insert(target);
+            }
+        } else if path.join("conf.toml").exists() {
+            exec_env_paths.push(path);
+        } else if path.is_dir()
+            && !path.file_name().is_some_and(|name| {
+                name == CREATING_EXEC_ENV_DIR
+                    || prefixes_dir == data_dir
+                        && DATA_DIR_ENTRIES.iter().any(|entry| name == *entry)
+            })
+        {
+            orphans.push((path, "no conf.toml"));
+        }
+    }
+    if named_dir.is_dir() {
+        for entry in fs::read_dir(&named_dir)? {
+            let path = entry?.path();
+            if !path.is_dir() || path.file_name() == Some(OsStr::new(CREATING_EXEC_ENV_DIR)) {
+                continue;
+            }
+            if !fs::canonicalize(&path).is_ok_and(|path| linked.contains(&path)) {
+                orphans.push((path.clone(), "named prefix without links"));
+            }
+            if path.join("conf.toml").exists() {
+                exec_env_paths.push(path);
+            }
+        }
+    }
+    let default_prefix_dir = data_dir.join("default-prefix");
+    if default_prefix_dir.is_dir() {
+        for entry in fs::read_dir(&default_prefix_dir)? {
+            let path = entry?.path();
+            if path.join("conf.toml").exists() {
+                exec_env_paths.push(path);
+            }
+        }
+    }
+    for exec_env_path in &exec_env_paths {
+        let exec_env_conf_path = exec_env_path.join("conf.toml");
+        let Ok(exec_conf) = fs::read(&exec_env_conf_path)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| parse_toml::<ExecEnv>(&data, &exec_env_conf_path))
+        else {
+            continue;
+        };
+        let snapshots_dir = exec_env_path.join("snapshots");
+        if snapshots_dir.is_dir() {
+            for entry in fs::read_dir(&snapshots_dir)? {
+                let path = entry?.path();
+                if !exec_conf
+                    .snapshots
+                    .iter()
+                    .any(|snapshot| path.file_name() == Some(OsStr::new(&snapshot.name)))
+                {
+                    orphans.push((path, "snapshot which is not recorded"));
+                }
+            }
+        }
+        if exec_env_path.join(".wine.rollback").exists() {
+            orphans.push((exec_env_path.join(".wine.rollback"), "interrupted rollback"));
+        }
+    }
+    let mut creating_dirs = vec![prefixes_dir.clone(), named_dir, data_dir.clone()];
+    creating_dirs.sort();
+    creating_dirs.dedup();
+    for creating_dir in creating_dirs
+        .iter()
+        .map(|dir| dir.join(CREATING_EXEC_ENV_DIR))
+    {
+        if !creating_dir.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(&creating_dir)? {
+            let path = entry?.path();
+            // the extension is the pid of the process which is creating the prefix.
+            let is_creating = path
+                .extension()
+                .and_then(|pid| pid.to_str())
+                .is_some_and(|pid| Path::new("/proc").join(pid).exists());
+            if !is_creating {
+                orphans.push((path, "interrupted creation"));
+            }
+        }
+    }
+    orphans.sort();
+    let orphans = orphans
+        .into_iter()
+        .map(|(path, reason)| {
+            let size = dir_size(&path).unwrap_or(0);
+            (path, reason, size)
+        })
+        .collect::<Vec<_>>();
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(
+                &orphans
+                    .iter()
+                    .map(|(path, reason, size)| serde_json::json!({
+                        "path": path,
+                        "reason": reason,
+                        "size": size,
+                    }))
+                    .collect::<Vec<_>>()
+            )?
+        );
+        return Ok(());
+    }
+    for (path, reason, size) in &orphans {
+        println!("{:>10} {} ({reason})", format_size(*size), path.display());
+    }
+    println!(
+        "Orphan dirs: {} ({})",
+        orphans.len(),
+        format_size(orphans.iter().map(|(_, _, size)| size).sum())
+    );
+    Ok(())
+}
+/// WINEPREFIX and the prefix environment of exe for a shell.
+/// wine and wineserver of proton are set to WINE and WINESERVER as winetricks uses them.
+fn load_shell_envs(exec_path: &Path) -> Result<(PathBuf, BTreeMap<String, String>)> {
+    let (data_dir, conf) = prepare()?;
+    let prefixes_dir = get_prefixes_dir(&data_dir, &conf)?;
+    let (exec_env_path, exec_conf) = load_existing_exec_env(exec_path, &data_dir, &prefixes_dir)?;
+    unlock_exec_env();
+    let wine_prefix = get_wine_prefix(&exec_env_path, &exec_conf);
+    let mut envs = get_prefix_envs(&conf, &exec_conf, &wine_prefix)?;
+    envs.extend(exec_conf.runner.get().tricks_envs());
+    Ok((wine_prefix, envs))
+}
+fn shell_env(exec_path: PathBuf) -> Result<()> {
+    let (wine_prefix, envs) = load_shell_envs(&exec_path)?;
+    println!(
+        "export {WINEPREFIX}={}",
+        shell_quote(&wine_prefix.to_string_lossy())
+    );
+    for (key, value) in envs {
+        println!("export {key}={}", shell_quote(&value));
+    }
+    Ok(())
+}
+fn shell(exec_path: PathBuf) -> Result<()> {
+    let (wine_prefix, envs) = load_shell_envs(&exec_path)?;
+    let shell = env::var_os("SHELL").unwrap_or_else(|| OsString::from("/bin/sh"));
+    println!(
+        "Start {} in {}",
+        shell.to_string_lossy(),
+        wine_prefix.display()
+    );
+    new_command(&shell, Vec::<OsString>::new(), &wine_prefix, &envs)
+        .status()
+        .map_err(|e| spawn_error(&shell, e))?;
+    Ok(())
+}
+fn stop(exec_path: PathBuf) -> Result<()> {
+    let (data_dir, conf) = prepare()?;
+    let prefixes_dir = get_prefixes_dir(&data_dir, &conf)?;
+    let (exec_env_path, exec_conf) = load_existing_exec_env(&exec_path, &data_dir, &prefixes_dir)?;
+    unlock_exec_env();
+    if let Some(user) = &exec_conf.run_as {
+        set_run_as(user.clone());
+    }
+    let wine_prefix = get_wine_prefix(&exec_env_path, &exec_conf);
+    println!("Stop wineserver of {}", wine_prefix.display());
+    let status = exec_command(
+        CommandPurpose::Other,
+        exec_conf.runner.get().wineserver_command(),
+        ["-k"],
+        &wine_prefix,
+        &get_base_envs(&conf, &exec_conf),
+    )?;
+    if !status.success() {
+        return Err(fail(
+            Failure::Wine(status.code()),
+            format!("wineserver is not succeed {status}"),
+        ));
+    }
+    Ok(())
+}
+fn verify(exec_path: PathBuf) -> Result<()> {
+    let (data_dir, conf) = prepare()?;
+    let prefixes_dir = get_prefixes_dir(&data_dir, &conf)?;
+    let (exec_env_path, exec_conf) = load_existing_exec_env(&exec_path, &data_dir, &prefixes_dir)?;
+    unlock_exec_env();
+    if let Some(user) = &exec_conf.run_as {
+        set_run_as(user.clone());
+    }
+    let wine_prefix = get_wine_prefix(&exec_env_path, &exec_conf);
+    println!("Verify {}", wine_prefix.display());
+    let mut checks: Vec<(&str, Vec<String>)> = vec![];
+
+    let missing_entries = PREFIX_ENTRIES
+        .iter()
+        .filter(|entry| !wine_prefix.join(entry).exists())
+        .map(|entry| format!("{entry} is missing"))
+        .collect();
+    checks.push(("structure", missing_entries));
+
+    let broken_registries = ["system.reg", "user.reg"]
+        .iter()
+        .filter_map(|registry| {
+            check_registry(wine_prefix.join(registry))
+                .err()
+                .map(|e| format!("{registry} is broken: {e}"))
+        })
+        .collect();
+    checks.push(("registry", broken_registries));
+
+    let mut envs = get_base_envs(&conf, &exec_conf);
+    envs.extend(exec_conf.runner.get().tricks_envs());
+    let wineboot_problems = match exec_command(
+        CommandPurpose::Wine,
+        exec_conf.runner.get().wine_command(),
+        ["wineboot"],
+        &wine_prefix,
+        &envs,
+    ) {
+        Ok(status) if status.success() => vec![],
+        Ok(status) => vec![format!("wineboot is not succeed {status}")],
+        Err(e) => vec![e.to_string()],
+    };
+    checks.push(("wineboot", wineboot_problems));
+
+    // verbs of settings are not reported by list-installed.
+    let tricks_problems = get_winetricks_list(&data_dir).and_then(|list| {
+        let verbs = parse_winetricks_list(&list)
+            .into_iter()
+            .filter(|(category, _, _)| category != "settings")
+            .map(|(_, verb, _)| verb)
+            .collect::<HashSet<_>>();
+        let installed_tricks = get_installed_tricks(&conf, &exec_env_path, &exec_conf)?;
+        Ok(exec_conf
+            .executed_tricks
+            .iter()
+            .flat_map(|trick| trick.split_whitespace())
+            .filter(|verb| verbs.contains(*verb) && !installed_tricks.contains(*verb))
+            .map(|verb| format!("{verb} is executed but not installed"))
+            .collect())
+    });
+    checks.push((
+        "tricks",
+        tricks_problems.unwrap_or_else(|e| vec![e.to_string()]),
+    ));
+
+    let mut problem_count = 0;
+    for (name, problems) in &checks {
+        println!(
+            "{:<4} {name}",
+            if problems.is_empty() { "ok" } else { "NG" }
+        );
+        for problem in problems {
+            println!("     {problem}");
+        }
+        problem_count += problems.len();
+    }
+    if problem_count > 0 {
+        bail!("The prefix has {problem_count} problems.");
+    }
+    Ok(())
+}
+/// Check that the file is a wine registry whose lines are keys, values or comments.
+fn check_registry(path: impl AsRef<Path>) -> Result<()> {
+    let registry = fs::read_to_string(path)?;
+    let mut lines = registry.lines().enumerate();
+    if lines.next().map(|(_, line)| line) != Some("WINE REGISTRY Version 2") {
+        bail!("the header is not WINE REGISTRY Version 2");
+    }
+    let mut continued = false;
+    for (index, line) in lines {
+        let is_valid = continued
+            || line.is_empty()
+            || line.starts_with([';', '#', '"', '@'])
+            || (line.starts_with('[') && line.contains(']'));
+        if !is_valid {
+            bail!("line {} is invalid", index + 1);
+        }
+        continued = line.ends_with('\\');
+    }
+    Ok(())
+}
+/// Quote with single quotes for POSIX shells.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+fn snapshot(exec_path: PathBuf, name: Option<String>) -> Result<()> {
+    let (data_dir, conf) = prepare()?;
+    let prefixes_dir = get_prefixes_dir(&data_dir, &conf)?;
+    let (exec_env_path, mut exec_conf) =
+        load_existing_exec_env(&exec_path, &data_dir, &prefixes_dir)?;
+    if exec_conf.wine_prefix.is_some() {
+        bail!("Snapshot of the default prefix is not supported.");
+    }
+    let name = name.unwrap_or_else(|| {
+        (1..)
+            .map(|i| format!("snapshot-{i}"))
+            .find(|name| !exec_conf.snapshots.iter().any(|s| &s.name == name))
+            .unwrap_or_default()
+    });
+    if exec_conf.snapshots.iter().any(|s| s.name == name) {
+        bail!("snapshot {name} already exists.");
+    }
+    let snapshot_path = exec_env_path.join("snapshots").join(&name);
+    fs::create_dir_all(exec_env_path.join("snapshots"))?;
+    println!("Create snapshot {}", snapshot_path.display());
+    copy_dir_reflink(&exec_env_path.join(".wine"), &snapshot_path)?;
+    exec_conf.snapshots.push(Snapshot {
+        name,
+        created: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs(),
+        executed_tricks: exec_conf.executed_tricks.clone(),
+        win_envs: exec_conf.win_envs.clone(),
+    });
+    save_exec_env(exec_env_path.join("conf.toml"), &exec_conf)
+}
+/// Replace the wine prefix with a copy of the snapshot. The snapshot is kept.
+fn rollback(exec_path: PathBuf, name: Option<String>) -> Result<()> {
+    let (data_dir, conf) = prepare()?;
+    let prefixes_dir = get_prefixes_dir(&data_dir, &conf)?;
+    let (exec_env_path, mut exec_conf) =
+        load_existing_exec_env(&exec_path, &data_dir, &prefixes_dir)?;
+    let snapshot = match &name {
+        Some(name) => exec_conf.snapshots.iter().find(|s| &s.name == name),
+        None => exec_conf.snapshots.last(),
+    }
+    .cloned()
+    .ok_or_else(|| {
+        anyhow!(
+            "snapshot {} is not found. snapshots: {}",
+            name.as_deref().unwrap_or_default(),
+            exec_conf
+                .snapshots
+                .iter()
+                .map(|s| s.name.as_str())
+                .collect::<Vec<_>>()
+                .join(",")
+        )
+    })?;
+    let exec_env_wine_path = exec_env_path.join(".wine");
+    let old_wine_path = exec_env_path.join(".wine.rollback");
+    println!(
+        "Rollback {} to snapshot {}",
+        exec_env_wine_path.display(),
+        snapshot.name
+    );
+    if old_wine_path.exists() {
+        fs::remove_dir_all(&old_wine_path)?;
+    }
+    fs::rename(&exec_env_wine_path, &old_wine_path)?;
+    if let Err(e) = copy_dir_reflink(
+        &exec_env_path.join("snapshots").join(&snapshot.name),
+        &exec_env_wine_path,
+    ) {
+        if exec_env_wine_path.exists() {
+            fs::remove_dir_all(&exec_env_wine_path)?;
+        }
+        fs::rename(&old_wine_path, &exec_env_wine_path)?;
+        return Err(e);
+    }
+    fs::remove_dir_all(&old_wine_path)?;
+    exec_conf.executed_tricks = snapshot.executed_tricks;
+    exec_conf.win_envs = snapshot.win_envs;
+    exec_conf
+        .trick_durations
+        .retain(|trick, _| exec_conf.executed_tricks.contains(trick));
+    save_exec_env(exec_env_path.join("conf.toml"), &exec_conf)?;
+    update_wineprefix_symlink(&exec_env_path, &mut exec_conf, None)?;
+    Ok(())
+}
+/// Copy the dir with reflinks, or fully when the filesystem does not support them.
+fn copy_dir_reflink(from: &Path, to: &Path) -> Result<()> {
+    let command = OsStr::new("cp");
+    let status = Command::new(command)
+        .args(["-a", "--reflink=always"])
+        .arg(from)
+        .arg(to)
+        .stderr(Stdio::null())
+        .status()
+        .map_err(|e| spawn_error(command, e))?;
+    if status.success() {
+        return Ok(());
+    }
+    if to.exists() {
+        fs::remove_dir_all(to)?;
+    }
+    warn("The filesystem does not support reflink. The prefix is copied fully.");
+    let status = Command::new(command)
+        .arg("-a")
+        .arg(from)
+        .arg(to)
+        .status()
+        .map_err(|e| spawn_error(command, e))?;
+    if !status.success() {
+        bail!("cp is not succeed {status}");
+    }
+    Ok(())
+}
+fn prune_tricks(exec_path: PathBuf, tricks: Vec<String>, all: bool) -> Result<()> {
+    let (data_dir, conf) = prepare()?;
+    let prefixes_dir = get_prefixes_dir(&data_dir, &conf)?;
+    let (exec_env_path, mut exec_conf) =
+        load_existing_exec_env(&exec_path, &data_dir, &prefixes_dir)?;
+    if all {
+        exec_conf.executed_tricks.clear();
+        exec_conf.trick_durations.clear();
+    } else {
+        for trick in split_tricks(&tricks) {
+            if let Some(index) = exec_conf.executed_tricks.iter().position(|t| *t == trick) {
+                exec_conf.executed_tricks.remove(index);
+                exec_conf.trick_durations.remove(&trick);
+                println!("Forget winetricks {trick}");
+            } else {
+                println!("winetricks {trick} is not executed");
+            }
+        }
+    }
+    save_exec_env(exec_env_path.join("conf.toml"), &exec_conf)
+}
+/// Arguments for bwrap. The root filesystem is read-only and the home directory is hidden
+/// except the prefix, the exe directory and the additional binds.
+fn get_sandbox_args(
+    exec_env_path: impl AsRef<Path>,
+    exec_path: impl AsRef<Path>,
+    exec_conf: &ExecEnv,
+    binds: &[PathBuf],
+) -> Result<Vec<String>> {
+    let base_dirs = BaseDirs::new().ok_or_else(|| anyhow!("Can not get home dir."))?;
+    let mut sandbox_args = vec![
+        "--die-with-parent".to_string(),
+        "--ro-bind".to_string(),
+        "/".to_string(),
+        "/".to_string(),
+        "--dev-bind".to_string(),
+        "/dev".to_string(),
+        "/dev".to_string(),
+        "--proc".to_string(),
+        "/proc".to_string(),
+    ];
+    sandbox_args.extend([
+        "--tmpfs".to_string(),
+        base_dirs.home_dir().to_string_lossy().to_string(),
+    ]);
+    let mut push_bind = |option: &str, path: &Path| -> Result<()> {
+        let path = path::absolute(path)?.to_string_lossy().to_string();
+        sandbox_args.extend([option.to_string(), path.clone(), path]);
+        Ok(())
+    };
+    // wineserver makes its socket there, and wineserver -w outside of the sandbox finds it.
+    push_bind("--bind", &get_wineserver_dir())?;
+    if let Some(runtime_dir) = base_dirs.runtime_dir() {
+        push_bind("--bind", runtime_dir)?;
+    }
+    let exec_dir = path::absolute(exec_path)?
+        .parent()
+        .ok_or_else(|| anyhow!("Can not get exe dir."))?
+        .to_path_buf();
+    push_bind("--ro-bind", &exec_dir)?;
+    for winedllpath in &exec_conf.winedllpath {
+        push_bind("--ro-bind", winedllpath)?;
+    }
+    for bind in exec_conf.runner.get().sandbox_binds() {
+        push_bind("--ro-bind", &bind)?;
+    }
+    for bind in [exec_env_path.as_ref()]
+        .into_iter()
+        .chain(exec_conf.wine_prefix.as_deref())
+        .chain(binds.iter().map(|bind| bind.as_path()))
+    {
+        push_bind("--bind", bind)?;
+    }
+    Ok(sandbox_args)
+}
+fn copy_exe_into_prefix(exec_path: impl AsRef<Path>, copied_exe: impl AsRef<Path>) -> Result<()> {
+    let exec_path = exec_path.as_ref();
+    let copied_exe = copied_exe.as_ref();
+    if let Some(installers_dir) = copied_exe.parent()
+        && !installers_dir.exists()
+    {
+        fs::create_dir_all(installers_dir)?;
+    }
+    println!("Copy {} to {}", exec_path.display(), copied_exe.display());
+    fs::copy(exec_path, copied_exe)?;
+    Ok(())
+}
+/// Find the installer in a directory of setup files.
+fn find_installer(dir: impl AsRef<Path>) -> Result<PathBuf> {
+    let dir = dir.as_ref();
+    let mut installers = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if path.is_file()
+            && name.ends_with(".exe")
+            && INSTALLER_NAMES.iter().any(|n| name.starts_with(n))
+        {
+            installers.push(path);
+        }
+    }
+    installers.sort();
+    match installers.len() {
+        0 => bail!("installer is not found in {}", dir.display()),
+        1 => Ok(fs::canonicalize(&installers[0])?),
+        _ => bail!(
+            "multiple installers are found in {}. Please specify one of them:\n{}",
+            dir.display(),
+            installers
+                .iter()
+                .map(|i| format!("  {}", i.display()))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ),
+    }
+}
+/// Parse the fields of a shell link which are needed to run its target. ([MS-SHLLINK])
+fn parse_shell_link(path: impl AsRef<Path>) -> Result<ShellLink> {
+    const HAS_LINK_TARGET_ID_LIST: u32 = 0x1;
+    const HAS_LINK_INFO: u32 = 0x2;
+    const HAS_NAME: u32 = 0x4;
+    const HAS_RELATIVE_PATH: u32 = 0x8;
+    const HAS_WORKING_DIR: u32 = 0x10;
+    const HAS_ARGUMENTS: u32 = 0x20;
+    const IS_UNICODE: u32 = 0x80;
+    const VOLUME_ID_AND_LOCAL_BASE_PATH: u32 = 0x1;
+    let path = path.as_ref();
+    let data = fs::read(path)?;
+    let invalid = || anyhow!("{} is not a valid shortcut.", path.display());
+    let u16_at = |offset: usize| -> Result<usize> {
+        let bytes = data.get(offset..offset + 2).ok_or_else(invalid)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+    };
+    let u32_at = |offset: usize| -> Result<u32> {
+        let bytes = data.get(offset..offset + 4).ok_or_else(invalid)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+    let c_string_at = |offset: usize| -> Result<String> {
+        let bytes = data.get(offset..).ok_or_else(invalid)?;
+        let len = bytes.iter().position(|b| *b == 0).ok_or_else(invalid)?;
+        Ok(String::from_utf8_lossy(&bytes[..len]).to_string())
+    };
+    if u32_at(0)? != 0x4c {
+        return Err(invalid());
+    }
+    let flags = u32_at(0x14)?;
+    let mut offset = 0x4c;
+    if flags & HAS_LINK_TARGET_ID_LIST != 0 {
+        offset += 2 + u16_at(offset)?;
+    }
+    let mut shell_link = ShellLink::default();
+    if flags & HAS_LINK_INFO != 0 {
+        let link_info_flags = u32_at(offset + 8)?;
+        if link_info_flags & VOLUME_ID_AND_LOCAL_BASE_PATH != 0 {
+            let local_base_path = c_string_at(offset + u32_at(offset + 16)? as usize)?;
+            let common_path_suffix = c_string_at(offset + u32_at(offset + 24)? as usize)?;
+            shell_link.target = Some(local_base_path + &common_path_suffix);
+        }
+        offset += u32_at(offset)? as usize;
+    }
+    let read_string = |offset: &mut usize| -> Result<String> {
+        let len = u16_at(*offset)?;
+        *offset += 2;
+        let string = if flags & IS_UNICODE != 0 {
+            let bytes = data.get(*offset..*offset + len * 2).ok_or_else(invalid)?;
+            *offset += len * 2;
+            String::from_utf16_lossy(
+                &bytes
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .collect::<Vec<_>>(),
+            )
+        } else {
+            let bytes = data.get(*offset..*offset + len).ok_or_else(invalid)?;
+            *offset += len;
+            String::from_utf8_lossy(bytes).to_string()
+        };
+        Ok(string)
+    };
+    if flags & HAS_NAME != 0 {
+        read_string(&mut offset)?;
+    }
+    if flags & HAS_RELATIVE_PATH != 0 {
+        shell_link.relative_path = Some(read_string(&mut offset)?);
+    }
+    if flags & HAS_WORKING_DIR != 0 {
+        shell_link.working_dir = Some(read_string(&mut offset)?);
+    }
+    if flags & HAS_ARGUMENTS != 0 {
+        shell_link.arguments = Some(read_string(&mut offset)?);
+    }
+    Ok(shell_link)
+}
+/// Host path of the shortcut target. The target path is tried before the relative path.
+fn resolve_shell_link_target(
+    shell_link: &ShellLink,
+    shell_link_path: impl AsRef<Path>,
+    wine_prefix: impl AsRef<Path>,
+) -> Result<PathBuf> {
+    let shell_link_path = shell_link_path.as_ref();
+    let target = shell_link
+        .target
+        .iter()
+        .map(|target| from_windows_path(target, &wine_prefix))
+        .chain(shell_link.relative_path.iter().filter_map(|relative_path| {
+            let relative_path = relative_path.replace('\\', "/");
+            Some(shell_link_path.parent()?.join(relative_path))
+        }))
+        .find(|target| target.is_file());
+    target.ok_or_else(|| {
+        anyhow!(
+            "Can not resolve the target of {}. (target:{}, relative path:{})",
+            shell_link_path.display(),
+            shell_link.target.as_deref().unwrap_or("none"),
+            shell_link.relative_path.as_deref().unwrap_or("none")
+        )
+    })
+}
+/// Split windows command line arguments. Double quotes group whitespaces.
+fn split_command_line(command_line: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut arg = None::<String>;
+    let mut quoted = false;
+    for c in command_line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                arg.get_or_insert_default();
+            }
+            c if c.is_whitespace() && !quoted => args.extend(arg.take()),
+            c => arg.get_or_insert_default().push(c),
+        }
+    }
+    args.extend(arg);
+    args
+}
+fn read_exe_kind(exec_path: impl AsRef<Path>) -> Result<ExeKind> {
+    let mut header = vec![];
+    File::open(exec_path)?.take(4096).read_to_end(&mut header)?;
+    if !header.starts_with(b"MZ") || header.len() < 0x40 {
+        return Ok(ExeKind::Other);
+    }
+    let new_header_offset =
+        u32::from_le_bytes([header[0x3c], header[0x3d], header[0x3e], header[0x3f]]) as usize;
+    Ok(match header.get(new_header_offset..new_header_offset + 2) {
+        Some(b"PE") => ExeKind::Pe,
+        Some(b"NE") => ExeKind::Ne,
+        _ => ExeKind::Other,
+    })
+}
+fn read_pe_info(exec_path: impl AsRef<Path>) -> Result<PeInfo> {
+    let exec_path = exec_path.as_ref();
+    let invalid = || anyhow!("PE header of {} is broken.", exec_path.display());
+    let mut file = File::open(exec_path)?;
+    let mut header = vec![];
+    (&mut file).take(4096).read_to_end(&mut header)?;
+    let u16_at = |data: &[u8], offset: usize| {
+        data.get(offset..offset + 2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+            .ok_or_else(invalid)
+    };
+    let u32_at = |data: &[u8], offset: usize| {
+        data.get(offset..offset + 4)
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .ok_or_else(invalid)
+    };
+    let pe_offset = u32_at(&header, 0x3c)? as usize;
+    let machine = u16_at(&header, pe_offset + 4)?;
+    let section_count = u16_at(&header, pe_offset + 6)? as usize;
+    let optional_header_size = u16_at(&header, pe_offset + 20)? as usize;
+    let optional_header = pe_offset + 24;
+    let (bits, data_directories) = match u16_at(&header, optional_header)? {
+        0x10b => (32, optional_header + 96),
+        0x20b => (64, optional_header + 112),
+        magic => bail!("Unknown PE optional header magic 0x{magic:x}."),
+    };
+    let version_at = |offset: usize| -> Result<String> {
+        Ok(format!(
+            "{}.{}",
+            u16_at(&header, offset)?,
+            u16_at(&header, offset + 2)?
+        ))
+    };
+    let mut info = PeInfo {
+        machine: match machine {
+            0x14c => "x86".to_string(),
+            0x8664 => "x86_64".to_string(),
+            0x1c4 => "arm".to_string(),
+            0xaa64 => "arm64".to_string(),
+            machine => format!("0x{machine:04x}"),
+        },
+        bits,
+        subsystem: match u16_at(&header, optional_header + 68)? {
+            1 => "native".to_string(),
+            2 => "gui".to_string(),
+            3 => "console".to_string(),
+            subsystem => subsystem.to_string(),
+        },
+        linker_version: format!(
+            "{}.{}",
+            header.get(optional_header + 2).ok_or_else(invalid)?,
+            header.get(optional_header + 3).ok_or_else(invalid)?
+        ),
+        os_version: version_at(optional_header + 40)?,
+        subsystem_version: version_at(optional_header + 48)?,
+        execution_level: None,
+        supported_os: vec![],
+    };
+    // the manifest is the resource of type 24 in the resource directory, which is entry 2.
+    let directory_count = u32_at(&header, data_directories - 4)?;
+    let resource_rva = u32_at(&header, data_directories + 16)?;
+    if directory_count <= 2 || resource_rva == 0 {
+        return Ok(info);
+    }
+    let sections = optional_header + optional_header_size;
+    let Some((resource_offset, resource_size)) = (0..section_count)
+        .map(|i| -> Result<_> {
+            let section = sections + i * 40;
+            Ok((
+                u32_at(&header, section + 12)?,
+                u32_at(&header, section + 8)?.max(u32_at(&header, section + 16)?),
+                u32_at(&header, section + 20)?,
+                u32_at(&header, section + 16)?,
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .find(|(address, size, _, _)| {
+            address
+                .checked_add(*size)
+                .is_some_and(|end| (*address..end).contains(&resource_rva))
+        })
+        .map(|(address, _, offset, raw_size)| -> Result<_> {
+            Ok((
+                offset
+                    .checked_add(resource_rva - address)
+                    .ok_or_else(invalid)?,
+                raw_size - (resource_rva - address).min(raw_size),
+            ))
+        })
+        .transpose()?
+    else {
+        return Ok(info);
+    };
+    let mut resource = vec![];
+    file.seek(io::SeekFrom::Start(resource_offset.into()))?;
+    file.take(resource_size.into()).read_to_end(&mut resource)?;
+    // type, name and language are nested directories. The first name and language are used.
+    let mut entry = 0;
+    for id in [Some(24), None, None] {
+        let named_count = u16_at(&resource, entry + 12)? as usize;
+        let id_count = u16_at(&resource, entry + 14)? as usize;
+        let Some(offset) = (0..named_count + id_count)
+            .map(|i| {
+                Ok((
+                    u32_at(&resource, entry + 16 + i * 8)?,
+                    u32_at(&resource, entry + 20 + i * 8)?,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .find(|(name, _)| id.is_none_or(|id| *name == id))
+            .map(|(_, offset)| offset)
+        else {
+            return Ok(info);
+        };
+        entry = (offset & 0x7fffffff) as usize;
+    }
+    let manifest_start = u32_at(&resource, entry)?
+        .checked_sub(resource_rva)
+        .ok_or_else(invalid)? as usize;
+    let manifest_size = u32_at(&resource, entry + 4)? as usize;
+    let manifest = String::from_utf8_lossy(
+        resource
+            .get(
+                manifest_start
+                    ..manifest_start
+                        .checked_add(manifest_size)
+                        .ok_or_else(invalid)?,
+            )
+            .ok_or_else(invalid)?,
+    )
+    .to_string();
+    info.execution_level = manifest
+        .split("<requestedExecutionLevel")
+        .nth(1)
+        .and_then(|tag| xml_attribute(tag, "level"));
+    info.supported_os = manifest
+        .split("<supportedOS")
+        .skip(1)
+        .filter_map(|tag| xml_attribute(tag, "Id"))
+        .map(|id| {
+            match id.to_ascii_lowercase().as_str() {
+                "{e2011457-1546-43c5-a5fe-008deee3d3f0}" => "Windows Vista",
+                "{35138b9a-5d96-4fbd-8e2d-a2440225f93a}" => "Windows 7",
+                "{4a2f28e3-53b9-4441-ba9c-d69d4a4a6e38}" => "Windows 8",
+                "{1f676c76-80e1-4239-95bb-83d0f6d0da78}" => "Windows 8.1",
+                "{8e0f7a12-bfb3-4fe8-b9a5-48fd50a15a9a}" => "Windows 10",
+                _ => return id,
+            }
+            .to_string()
+        })
+        .collect();
+    Ok(info)
+}
+/// Value of the attribute in the text after the tag name.
+fn xml_attribute(tag: &str, name: &str) -> Option<String> {
+    let tag = &tag[..tag.find('>')?];
+    let value = tag.split(&format!("{name}=")).nth(1)?;
+    let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    value[1..]
+        .split(quote)
+        .next()
+        .map(|value| value.to_string())
+}
+fn probe(exec_path: PathBuf, json: bool) -> Result<()> {
+    check_exec_path(&exec_path)?;
+    if read_exe_kind(&exec_path)? != ExeKind::Pe {
+        bail!(
+            "{} is not a 32-bit or 64-bit windows exe.",
+            exec_path.display()
+        );
+    }
+    let info = read_pe_info(&exec_path)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+        return Ok(());
+    }
+    println!("Machine: {} ({}-bit)", info.machine, info.bits);
+    println!("Subsystem: {}", info.subsystem);
+    println!("Linker version: {}", info.linker_version);
+    println!("OS version: {}", info.os_version);
+    println!("Subsystem version: {}", info.subsystem_version);
+    if let Some(execution_level) = &info.execution_level {
+        println!("Execution level: {execution_level}");
+    }
+    if !info.supported_os.is_empty() {
+        println!("Supported OS: {}", info.supported_os.join(", "));
+    }
+    Ok(())
+}
+/// Fail when the wine of the prefix is found but it does not have the 16-bit support.
+/// 16-bit exe needs krnl386.exe16, which wine builds without 32-bit support do not ship.
+fn check_wine_16bit(exec_conf: &ExecEnv) -> Result<()> {
+    let wine_command = PathBuf::from(exec_conf.runner.get().wine_command());
+    let wine_path = if wine_command.components().count() > 1 {
+        Some(wine_command)
+    } else {
+        env::var_os("PATH").and_then(|paths| {
+            env::split_paths(&paths)
+                .map(|path| path.join(&wine_command))
+                .find(|path| path.is_file())
+        })
+    };
+    let Some(wine_path) = wine_path else {
+        return Ok(());
+    };
+    let wine_path = fs::canonicalize(&wine_path).unwrap_or(wine_path);
+    let Some(root_dir) = wine_path.parent().and_then(|bin_dir| bin_dir.parent()) else {
+        return Ok(());
+    };
+    let has_16bit = ["lib", "lib32", "lib64", "lib/i386-linux-gnu"]
+        .iter()
+        .flat_map(|lib| {
+            ["wine/i386-windows", "wine/fakedlls", "wine"]
+                .iter()
+                .map(move |dir| root_dir.join(lib).join(dir).join("krnl386.exe16"))
+        })
+        .any(|path| path.exists());
+    if !has_16bit {
+        bail!(
+            "exe is a 16-bit windows exe, but {} does not have the 16-bit support. \
+             Please use a wine build with 32-bit support and a 32-bit prefix. (WINEARCH=win32)",
+            wine_path.display()
+        );
+    }
+    Ok(())
+}
+fn check_exec_path(exec_path: impl AsRef<Path>) -> Result<()> {
+    let exec_path = exec_path.as_ref();
+    if !exec_path.exists() {
+        bail!("exe is not found: {}", exec_path.display());
+    }
+    if !exec_path.is_file() {
+        bail!("exe is not a file: {}", exec_path.display());
+    }
+    Ok(())
+}
+fn resolve_exec_env_path(
+    exec_path: impl AsRef<Path>,
+    data_dir: impl AsRef<Path>,
+    prefixes_dir: impl AsRef<Path>,
+) -> Result<PathBuf> {
+    let exec_path = exec_path.as_ref();
+    let prefixes_dir = prefixes_dir.as_ref();
+    if let Some(exec_env_path) = get_base_env_dir_from_exec_path(exec_path, data_dir, prefixes_dir)
+    {
+        Ok(exec_env_path)
+    } else {
+        get_env_dir(exec_path, prefixes_dir)
+    }
+}
+fn explain_exec_env_resolution(
+    args: &Args,
+    exec_path: impl AsRef<Path>,
+    data_dir: impl AsRef<Path>,
+    prefixes_dir: impl AsRef<Path>,
+) -> Result<()> {
+    let exec_path = exec_path.as_ref();
+    let prefixes_dir = prefixes_dir.as_ref();
+    println!("Resolve prefix:");
+    println!("  exe: {}", exec_path.display());
+    if let Ok(canonical_path) = fs::canonicalize(exec_path) {
+        println!("  canonicalized exe: {}", canonical_path.display());
+    }
+    println!("  prefixes dir: {}", prefixes_dir.display());
+    if args.use_default_prefix {
+        println!(
+            "  use the default prefix: {}",
+            get_default_wine_prefix()?.display()
+        );
+        return Ok(());
+    }
+    if args.prefix_name.is_some() {
+        let named_env_dir = resolve_args_exec_env_path(args, exec_path, data_dir, prefixes_dir)?;
+        if named_env_dir.exists() {
+            println!("  use named env: {}", named_env_dir.display());
+        } else {
+            println!("  allocate new named env: {}", named_env_dir.display());
+        }
+        println!(
+            "  link env by exe name to it: {}",
+            get_env_dir(exec_path, prefixes_dir)?.display()
+        );
+        return Ok(());
+    }
+    let wine_prefix_dir = exec_path
+        .ancestors()
+        .skip(1)
+        .find(|dir| dir.file_name().is_some_and(|n| n == ".wine"));
+    match wine_prefix_dir {
+        None => println!("  exe is not in a .wine directory, so it is not in a base env."),
+        Some(wine_prefix_dir) => {
+            println!("  exe is in {}", wine_prefix_dir.display());
+            let wine_prefix_dir = path::absolute(wine_prefix_dir)?;
+            if !get_env_roots(&data_dir, prefixes_dir)?
+                .iter()
+                .any(|env_root| wine_prefix_dir.starts_with(env_root))
+            {
+                println!(
+                    "  the .wine directory is not under the prefixes dir or the named prefixes dir."
+                );
+            } else if !wine_prefix_dir
+                .parent()
+                .is_some_and(|dir| dir.join("conf.toml").exists())
+            {
+                println!("  the .wine directory has no conf.toml next to it.");
+            }
+        }
+    }
+    if let Some(base_env_dir) = get_base_env_dir_from_exec_path(exec_path, &data_dir, prefixes_dir)
+    {
+        println!("  use base env: {}", base_env_dir.display());
+    } else {
+        let env_dir = get_env_dir(exec_path, prefixes_dir)?;
+        if env_dir.exists() {
+            println!("  use env by exe name: {}", env_dir.display());
+        } else {
+            println!("  allocate new env by exe name: {}", env_dir.display());
+        }
+    }
+    Ok(())
+}
+/// Exec env dir which `run` uses for exe with --use-default-prefix and --prefix-name.
+fn resolve_args_exec_env_path(
+    args: &Args,
+    exec_path: impl AsRef<Path>,
+    data_dir: impl AsRef<Path>,
+    prefixes_dir: impl AsRef<Path>,
+) -> Result<PathBuf> {
+    if args.use_default_prefix {
+        get_default_exec_env_path(data_dir)
+    } else if let Some(prefix_name) = &args.prefix_name {
+        Ok(path::absolute(
+            data_dir.as_ref().join("named").join(prefix_name),
+        )?)
+    } else {
+        resolve_exec_env_path(exec_path, &data_dir, prefixes_dir)
+    }
+}
+/// Load the exec env of exe without creating it.
+fn load_existing_exec_env(
+    exec_path: impl AsRef<Path>,
+    data_dir: impl AsRef<Path>,
+    prefixes_dir: impl AsRef<Path>,
+) -> Result<(PathBuf, ExecEnv)> {
+    let exec_path = exec_path.as_ref();
+    let exec_env_path = resolve_exec_env_path(exec_path, data_dir, prefixes_dir)?;
+    let exec_env_conf_path = exec_env_path.join("conf.toml");
+    if !exec_env_conf_path.exists() {
+        bail!("prefix is not created yet for {}", exec_path.display());
+    }
+    lock_exec_env(&exec_env_path)?;
+    let exec_conf = parse_toml(&fs::read(&exec_env_conf_path)?, &exec_env_conf_path)?;
+    Ok((exec_env_path, exec_conf))
+}
+/// Load the exec env of exe, creating it when it does not exist yet.
+/// Returns whether the exec env is newly created.
+fn load_exec_env(
+    exec_path: impl AsRef<Path>,
+    data_dir: impl AsRef<Path>,
+    prefixes_dir: impl AsRef<Path>,
+) -> Result<(PathBuf, ExecEnv, bool)> {
+    let exec_env_path = resolve_exec_env_path(exec_path, data_dir, prefixes_dir)?;
+    load_exec_env_at(exec_env_path)
+}
+/// Load the exec env which records the default wine prefix.
+/// The default prefix is $WINEPREFIX or ~/.wine, and it is shared by all exes.
+/// It is never reported as newly created since the user manages it.
+fn load_default_exec_env(data_dir: impl AsRef<Path>) -> Result<(PathBuf, ExecEnv, bool)> {
+    let data_dir = data_dir.as_ref();
+    let wine_prefix = get_default_wine_prefix()?;
+    let exec_env_path = get_default_exec_env_path(data_dir)?;
+    // an older version records every default prefix in default-prefix/conf.toml.
+    let legacy_conf_path = data_dir.join("default-prefix").join("conf.toml");
+    if !exec_env_path.exists()
+        && fs::read(&legacy_conf_path)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| parse_toml::<ExecEnv>(&data, &legacy_conf_path))
+            .is_ok_and(|exec_conf| exec_conf.wine_prefix.as_ref() == Some(&wine_prefix))
+    {
+        fs::create_dir_all(exec_env_path.join(".wine"))?;
+        fs::rename(&legacy_conf_path, exec_env_path.join("conf.toml"))?;
+    }
+    let (exec_env_path, mut exec_conf, _) = load_exec_env_at(exec_env_path)?;
+    if exec_conf.wine_prefix.is_none() {
+        exec_conf.wine_prefix = Some(wine_prefix);
+        save_exec_env(exec_env_path.join("conf.toml"), &exec_conf)?;
+    }
+    Ok((exec_env_path, exec_conf, false))
+}
+/// Exec env dir of the default wine prefix. Each prefix path has its own dir so that the
+/// executed tricks of a prefix are not taken as those of another.
+fn get_default_exec_env_path(data_dir: impl AsRef<Path>) -> Result<PathBuf> {
+    let wine_prefix = get_default_wine_prefix()?;
+    let hash = sha256_hex(wine_prefix.as_os_str().as_encoded_bytes());
+    Ok(data_dir.as_ref().join("default-prefix").join(&hash[..16]))
+}
+/// $WINEPREFIX or ~/.wine.
+fn get_default_wine_prefix() -> Result<PathBuf> {
+    let wine_prefix = match env::var_os(WINEPREFIX) {
+        Some(wine_prefix) if !wine_prefix.is_empty() => PathBuf::from(wine_prefix),
+        _ => BaseDirs::new()
+            .ok_or_else(|| anyhow!("Can not get home dir."))?
+            .home_dir()
+            .join(".wine"),
+    };
+    Ok(path::absolute(wine_prefix)?)
+}
+fn load_exec_env_at(exec_env_path: PathBuf) -> Result<(PathBuf, ExecEnv, bool)> {
+    let exec_env_conf_path = exec_env_path.join("conf.toml");
+    let created = !exec_env_path.exists() && create_exec_env_dir(&exec_env_path)?;
+    lock_exec_env(&exec_env_path)?;
+    // a dir without conf.toml is left by an older version which is interrupted.
+    let created = created || !exec_env_conf_path.exists();
+
+    let mut exec_env_conf_buf = vec![];
+    {
+        let mut exec_env_conf_file = if exec_env_conf_path.exists() {
+            File::open(&exec_env_conf_path)?
+        } else {
+            File::create_new(&exec_env_conf_path)?
+        };
+        exec_env_conf_file.read_to_end(&mut exec_env_conf_buf)?;
+    }
+    let exec_conf = parse_toml(&exec_env_conf_buf, &exec_env_conf_path)?;
+    let exec_env_wine_path = exec_env_path.join(".wine");
+    if !exec_env_wine_path.exists() {
+        fs::create_dir_all(&exec_env_wine_path)?;
+    }
+    Ok((exec_env_path, exec_conf, created))
+}
+/// Create the exec env dir with conf.toml in a temporary dir and rename it into place,
+/// so that an interrupted creation does not leave an exec env dir without conf.toml.
+/// Returns false when another process has created it meanwhile.
+fn create_exec_env_dir(exec_env_path: &Path) -> Result<bool> {
+    let parent = exec_env_path
+        .parent()
+        .ok_or_else(|| anyhow!("Can not get parent of {}.", exec_env_path.display()))?;
+    let name = exec_env_path
+        .file_name()
+        .ok_or_else(|| anyhow!("Can not get file name."))?;
+    let creating_dir = parent.join(CREATING_EXEC_ENV_DIR);
+    let mut temp_path = creating_dir.join(name);
+    temp_path
+        .as_mut_os_string()
+        .push(format!(".{}", std::process::id()));
+    if temp_path.exists() {
+        fs::remove_dir_all(&temp_path)?;
+    }
+    fs::create_dir_all(temp_path.join(".wine"))?;
+    fs::write(
+        temp_path.join("conf.toml"),
+        toml::to_string_pretty(&ExecEnv::default())?.as_bytes(),
+    )?;
+    let created = match fs::rename(&temp_path, exec_env_path) {
+        Ok(()) => true,
+        Err(_) if exec_env_path.join("conf.toml").exists() => {
+            fs::remove_dir_all(&temp_path)?;
+            false
+        }
+        Err(e) => {
+            fs::remove_dir_all(&temp_path)?;
+            return Err(anyhow!(e).context(format!(
+                "Can not create prefix {}.",
+                exec_env_path.display()
+            )));
+        }
+    };
+    // the dir is left when other processes are creating prefixes.
+    let _ = fs::remove_dir(&creating_dir);
+    Ok(created)
+}
+/// Lock the exec env until the process exits or `unlock_exec_env` is called.
+/// conf.toml is read after the lock so that the updates of other processes are not lost.
+/// The lock of the previously loaded exec env is released since a process locks one at a time.
+fn lock_exec_env(exec_env_path: impl AsRef<Path>) -> Result<()> {
+    let exec_env_path = exec_env_path.as_ref();
+    unlock_exec_env();
+    let lock_file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(exec_env_path.join("conf.lock"))?;
+    match lock_file.try_lock() {
+        Ok(()) => {}
+        Err(fs::TryLockError::WouldBlock) => {
+            println!(
+                "Wait for another process which uses prefix {}...",
+                exec_env_path.display()
+            );
+            match LOCK_TIMEOUT.get() {
+                Some(lock_timeout) => {
+                    let started = Instant::now();
+                    loop {
+                        match lock_file.try_lock() {
+                            Ok(()) => break,
+                            Err(fs::TryLockError::WouldBlock) => {}
+                            Err(fs::TryLockError::Error(e)) => return Err(e.into()),
+                        }
+                        if started.elapsed() >= *lock_timeout {
+                            return Err(fail(
+                                Failure::Locked,
+                                format!(
+                                    "prefix {} is used by another process.",
+                                    exec_env_path.display()
+                                ),
+                            ));
+                        }
+                        thread::sleep(Duration::from_millis(100));
+                    }
+                }
+                None => lock_file.lock()?,
+            }
+        }
+        Err(fs::TryLockError::Error(e)) => return Err(e.into()),
+    }
+    *EXEC_ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner()) = Some(lock_file);
+    Ok(())
+}
+fn unlock_exec_env() {
+    EXEC_ENV_LOCK
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take();
+}
+fn get_wine_prefix(exec_env_path: impl AsRef<Path>, exec_conf: &ExecEnv) -> PathBuf {
+    if let Some(wine_prefix) = &exec_conf.wine_prefix {
+        return wine_prefix.clone();
+    }
+    exec_conf
+        .runner
+        .get()
+        .wine_prefix(exec_env_path.as_ref().join(".wine"))
+}
+fn reset_prefix(
+    exec_env_path: impl AsRef<Path>,
+    exec_conf: &mut ExecEnv,
+    conf: &Config,
+    tricks_args: &TricksArgs,
+) -> Result<()> {
+    let exec_env_path = exec_env_path.as_ref();
+    let exec_env_wine_path = exec_env_path.join(".wine");
+    println!("Reset prefix {}", exec_env_wine_path.display());
+    if exec_env_wine_path.exists() {
+        // the files belong to the user of --reexec-as, who removes them.
+        if RUN_AS.get().is_some() {
+            let status = exec_command(
+                CommandPurpose::Other,
+                "find",
+                [
+                    exec_env_wine_path.as_os_str(),
+                    OsStr::new("-mindepth"),
+                    OsStr::new("1"),
+                    OsStr::new("-delete"),
+                ],
+                &exec_env_wine_path,
+                &BTreeMap::new(),
+            )?;
+            if !status.success() {
+                bail!("Can not remove {}: {status}", exec_env_wine_path.display());
+            }
+        }
+        fs::remove_dir_all(&exec_env_wine_path)?;
+    }
+    fs::create_dir_all(&exec_env_wine_path)?;
+    if let Some(user) = RUN_AS.get() {
+        give_wine_prefix(exec_env_path, user)?;
+    }
+    let wine_prefix = get_wine_prefix(exec_env_path, exec_conf);
+    let status = exec_command(
+        CommandPurpose::Wine,
+        exec_conf.runner.get().wine_command(),
+        ["wineboot", "-i"],
+        &wine_prefix,
+        &get_base_envs(conf, exec_conf),
+    )?;
+    if !status.success() {
+        return Err(fail(
+            Failure::Wine(status.code()),
+            format!("wineboot is not succeed {status}"),
+        ));
+    }
+    // replayed in the order of the first execution.
+    let tricks = std::mem::take(&mut exec_conf.executed_tricks);
+    let win_envs = std::mem::take(&mut exec_conf.win_envs);
+    save_exec_env(exec_env_path.join("conf.toml"), exec_conf)?;
+    set_win_envs(
+        exec_env_path,
+        exec_conf,
+        conf,
+        &win_envs.into_iter().collect::<Vec<_>>(),
+    )?;
+    // replayed commands are always recorded, otherwise they are forgotten.
+    let tricks_args = TricksArgs {
+        no_tricks_record: false,
+        ..tricks_args.clone()
+    };
+    run_tricks(&tricks, exec_env_path, exec_conf, conf, &tricks_args)
+}
+/// Write windows environment variables which are changed into the registry of the prefix.
+fn set_win_envs(
+    exec_env_path: &Path,
+    exec_conf: &mut ExecEnv,
+    conf: &Config,
+    win_envs: &[(String, String)],
+) -> Result<()> {
+    let wine_prefix = get_wine_prefix(exec_env_path, exec_conf);
+    for (key, value) in win_envs {
+        if exec_conf.win_envs.get(key) == Some(value) {
+            continue;
+        }
+        println!("Set windows environment variable {key}");
+        let status = exec_command(
+            CommandPurpose::Wine,
+            exec_conf.runner.get().wine_command(),
+            [
+                "reg",
+                "add",
+                "HKCU\\Environment",
+                "/v",
+                key,
+                "/d",
+                value,
+                "/f",
+            ],
+            &wine_prefix,
+            &get_base_envs(conf, exec_conf),
+        )?;
+        if !status.success() {
+            return Err(fail(
+                Failure::Wine(status.code()),
+                format!("wine reg add is not succeed {key}, status:{status}"),
+            ));
+        }
+        exec_conf.win_envs.insert(key.clone(), value.clone());
+        save_exec_env(exec_env_path.join("conf.toml"), exec_conf)?;
+    }
+    Ok(())
+}
+fn run_tricks(
+    tricks: &[String],
+    exec_env_path: impl AsRef<Path>,
+    exec_conf: &mut ExecEnv,
+    conf: &Config,
+    tricks_args: &TricksArgs,
+) -> Result<()> {
+    let exec_env_path = exec_env_path.as_ref();
+    let exec_env_conf_path = exec_env_path.join("conf.toml");
+    let wine_prefix = get_wine_prefix(exec_env_path, exec_conf);
+    let mut tricks_envs = get_base_envs(conf, exec_conf);
+    tricks_envs.extend(exec_conf.runner.get().tricks_envs());
+    tricks_envs.extend(tricks_args.winetricks_env.clone());
+    let mut dependencies = default_trick_dependencies();
+    dependencies.extend(conf.trick_dependencies.clone());
+
+    println!("Resolve winetricks...");
+    let tricks = resolve_trick_dependencies(
+        split_tricks(tricks),
+        &exec_conf.executed_tricks,
+        &dependencies,
+    );
+    if tricks_args.confirm_tricks
+        && tricks
+            .iter()
+            .any(|trick| !exec_conf.executed_tricks.contains(trick))
+    {
+        for trick in &tricks {
+            if exec_conf.executed_tricks.contains(trick) {
+                println!("  installed {trick}");
+            } else {
+                println!("  new       {trick}");
+            }
+        }
+        if !confirm("Run winetricks?")? {
+            return Err(fail(Failure::Canceled, "winetricks is canceled."));
+        }
+    }
+    let tricks_lock_path = exec_env_path.join("tricks.lock");
+    let mut tricks_lock = None;
+    if !tricks.is_empty() && (tricks_args.locked || !tricks_args.no_tricks_record) {
+        let winetricks_version = get_winetricks_version(&wine_prefix, &tricks_envs)?;
+        let lock = if tricks_lock_path.exists() {
+            parse_toml::<TricksLock>(&fs::read(&tricks_lock_path)?, &tricks_lock_path)?
+        } else if tricks_args.locked {
+            return Err(fail(
+                Failure::Config,
+                format!("tricks.lock is not found in {}", exec_env_path.display()),
+            ));
+        } else {
+            TricksLock::default()
+        };
+        if tricks_args.locked {
+            if lock.winetricks_version != winetricks_version {
+                return Err(fail(
+                    Failure::Config,
+                    format!(
+                        "winetricks version does not match tricks.lock. locked:{}, current:{winetricks_version}",
+                        lock.winetricks_version
+                    ),
+                ));
+            }
+            let unlocked = tricks
+                .iter()
+                .filter(|trick| !lock.tricks.contains(trick))
+                .cloned()
+                .collect::<Vec<_>>();
+            if !unlocked.is_empty() {
+                return Err(fail(
+                    Failure::Config,
+                    format!(
+                        "winetricks commands are not in tricks.lock: {}",
+                        unlocked.join(",")
+                    ),
+                ));
+            }
+        }
+        // the lock keeps the version which the prefix is built with.
+        let winetricks_version =
+            if lock.winetricks_version.is_empty() || tricks_args.update_tricks_lock {
+                winetricks_version
+            } else {
+                if lock.winetricks_version != winetricks_version {
+                    warn(format!(
+                        "winetricks version {winetricks_version} is not {} in tricks.lock. \
+                     Use --update-tricks-lock to record it.",
+                        lock.winetricks_version
+                    ));
+                }
+                lock.winetricks_version.clone()
+            };
+        tricks_lock = Some(TricksLock {
+            winetricks_version,
+            ..lock
+        });
+    }
+    let timeout = tricks_args.winetricks_verb_timeout.map(Duration::from_secs);
+    let mut durations = vec![];
+    let mut skipped = vec![];
+    for trick in tricks {
+        if !exec_conf.executed_tricks.contains(&trick) {
+            let start = Instant::now();
+            let (status, stderr_tail) = exec_command_with_stderr_tail(
+                CommandPurpose::Winetricks,
+                "winetricks",
+                get_winetricks_args(&tricks_args.winetricks_arg, &trick),
+                &wine_prefix,
+                &tricks_envs,
+                timeout,
+            )?;
+            let error = match status {
+                None => {
+                    kill_wineserver(exec_conf, &wine_prefix, &tricks_envs);
+                    Some(fail(
+                        Failure::Timeout,
+                        format!(
+                            "winetricks is timed out {trick}, timeout:{}s\n{}",
+                            timeout.unwrap_or_default().as_secs(),
+                            stderr_tail.join("\n")
+                        ),
+                    ))
+                }
+                Some(status) if !status.success() => Some(fail(
+                    Failure::Winetricks,
+                    format!(
+                        "winetricks is not succeed {trick}, status:{status}\n{}{}",
+                        stderr_tail.join("\n"),
+                        esync_hint(&stderr_tail)
+                    ),
+                )),
+                Some(_) => None,
+            };
+            if let Some(error) = error {
+                if !tricks_args.keep_going {
+                    return Err(error);
+                }
+                warn(format!("{error}"));
+                skipped.push(trick);
+                continue;
+            }
+            let duration = start.elapsed();
+            durations.push((trick.clone(), duration));
+            if !tricks_args.no_tricks_record {
+                exec_conf.executed_tricks.push(trick.clone());
+                exec_conf
+                    .trick_durations
+                    .insert(trick.clone(), duration.as_secs_f64());
+                save_exec_env(&exec_env_conf_path, exec_conf)?;
+            }
+        }
+        if !tricks_args.no_tricks_record
+            && let Some(tricks_lock) = &mut tricks_lock
+            && !tricks_lock.tricks.contains(&trick)
+        {
+            tricks_lock.tricks.push(trick);
+            fs::write(
+                &tricks_lock_path,
+                toml::to_string_pretty(tricks_lock)?.as_bytes(),
+            )?;
+        }
+    }
+    if durations.len() > 1 {
+        durations.sort_by_key(|(_, duration)| std::cmp::Reverse(*duration));
+        println!("winetricks durations:");
+        for (trick, duration) in durations {
+            println!("  {:>8.1}s {trick}", duration.as_secs_f64());
+        }
+    }
+    if !skipped.is_empty() {
+        warn(format!(
+            "winetricks commands are skipped: {}",
+            skipped.join(",")
+        ));
+    }
+    Ok(())
+}
+fn check_wine_version(
+    constraint: &VersionConstraint,
+    exec_env_path: impl AsRef<Path>,
+    exec_conf: &ExecEnv,
+    conf: &Config,
+) -> Result<()> {
+    let command = exec_conf.runner.get().wine_command();
+    let output = new_command(
+        &command,
+        ["--version"],
+        get_wine_prefix(exec_env_path, exec_conf),
+        &get_base_envs(conf, exec_conf),
+    )
+    .stdout(Stdio::piped())
+    .stderr(Stdio::null())
+    .output()
+    .map_err(|e| spawn_error(&command, e))?;
+    let version_text = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let version = WineVersion::parse(&version_text)
+        .ok_or_else(|| anyhow!("Can not parse the version of wine: {version_text}"))?;
+    if !constraint.is_satisfied(&version) {
+        bail!(
+            "{version_text} does not satisfy the required wine version {}.",
+            constraint.text
+        );
+    }
+    Ok(())
+}
+fn get_winetricks_version(
+    wine_prefix: impl AsRef<Path>,
+    envs: &BTreeMap<String, String>,
+) -> Result<String> {
+    let command = OsStr::new("winetricks");
+    let output = new_command(command, ["--version"], wine_prefix, envs)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| spawn_error(command, e))?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_string())
+}
+/// Output of `winetricks list-all`. It is cached in the data dir.
+fn get_winetricks_list(data_dir: impl AsRef<Path>) -> Result<String> {
+    let cache_path = data_dir.as_ref().join(WINETRICKS_VERBS_CACHE);
+    let is_fresh = fs::metadata(&cache_path)
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|modified| {
+            modified
+                .elapsed()
+                .is_ok_and(|elapsed| elapsed < WINETRICKS_VERBS_CACHE_TTL)
+        });
+    if is_fresh {
+        return Ok(fs::read_to_string(&cache_path)?);
+    }
+    let command = OsStr::new("winetricks");
+    let output = Command::new(command)
+        .arg("list-all")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| spawn_error(command, e))?;
+    if !output.status.success() {
+        return Err(fail(
+            Failure::Winetricks,
+            format!("winetricks list-all is not succeed {}", output.status),
+        ));
+    }
+    let list = String::from_utf8_lossy(&output.stdout).to_string();
+    fs::write(&cache_path, &list)?;
+    Ok(list)
+}
+/// Parse `winetricks list-all` into (category, verb, description).
+fn parse_winetricks_list(list: &str) -> Vec<(String, String, String)> {
+    let mut category = String::new();
+    let mut verbs = vec![];
+    for line in list.lines() {
+        if line.starts_with("===") {
+            category = line
+                .trim_matches(|c: char| c == '=' || c.is_whitespace())
+                .to_string();
+        } else if let Some(verb) = line.split_whitespace().next() {
+            let description = line.trim_start()[verb.len()..].trim();
+            verbs.push((category.clone(), verb.to_string(), description.to_string()));
+        }
+    }
+    verbs
+}
+fn get_winetricks_verbs(data_dir: impl AsRef<Path>) -> Result<HashSet<String>> {
+    Ok(parse_winetricks_list(&get_winetricks_list(data_dir)?)
+        .into_iter()
+        .map(|(_, verb, _)| verb)
+        .collect())
+}
+/// Verbs of the winetricks category.
+/// settings is refused since its verbs override each other. (e.g. win7 and win10)
+fn get_category_tricks(category: &str, data_dir: impl AsRef<Path>) -> Result<Vec<String>> {
+    if category == "settings" {
+        bail!("winetricks category settings can not be run as a whole.");
+    }
+    let list = parse_winetricks_list(&get_winetricks_list(data_dir)?);
+    let tricks = list
+        .iter()
+        .filter(|(verb_category, _, _)| verb_category == category)
+        .map(|(_, verb, _)| verb.clone())
+        .collect::<Vec<_>>();
+    if tricks.is_empty() {
+        let mut categories = list
+            .into_iter()
+            .map(|(category, _, _)| category)
+            .collect::<Vec<_>>();
+        categories.dedup();
+        bail!(
+            "winetricks category {category} is not found. categories: {}",
+            categories.join(",")
+        );
+    }
+    Ok(tricks)
+}
+/// Fail when a winetricks command is not a verb of winetricks.
+/// Settings like `vd=1024x768` are accepted by the setting name.
+fn check_tricks(tricks: &[String], data_dir: impl AsRef<Path>) -> Result<()> {
+    let tricks = split_tricks(tricks);
+    if tricks.is_empty() {
+        return Ok(());
+    }
+    let verbs = get_winetricks_verbs(data_dir)?;
+    for trick in &tricks {
+        let verb = trick.split_whitespace().next().unwrap_or_default();
+        let is_known = verbs.contains(verb)
+            || verb.split_once('=').is_some_and(|(name, _)| {
+                verbs.iter().any(|known| {
+                    known
+                        .split_once('=')
+                        .is_some_and(|(known, _)| known == name)
+                })
+            });
+        if is_known {
+            continue;
+        }
+        match verbs
+            .iter()
+            .map(|known| (edit_distance(verb, known), known))
+            .filter(|(distance, _)| *distance <= 2)
+            .min()
+        {
+            Some((_, suggestion)) => {
+                bail!("Unknown winetricks command {verb}. Did you mean {suggestion}?")
+            }
+            None => bail!("Unknown winetricks command {verb}."),
+        }
+    }
+    Ok(())
+}
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if a == *b {
+                previous
+            } else {
+                previous.min(row[j]).min(current) + 1
+            };
+            previous = current;
+        }
+    }
+    row[b.len()]
+}
+/// Ask yes or no. Always yes with --yes or when stdin is not a terminal.
+fn confirm(message: &str) -> Result<bool> {
+    if ASSUME_YES.get().copied().unwrap_or(false) || !io::stdin().is_terminal() {
+        return Ok(true);
+    }
+    print!("{message} [y/N] ");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+/// Ask yes or no before removing or overwriting files. Unlike confirm, never yes when stdin is
+/// not a terminal unless --yes is given.
+fn confirm_destructive(message: &str) -> Result<bool> {
+    if !ASSUME_YES.get().copied().unwrap_or(false) && !io::stdin().is_terminal() {
+        return Err(fail(
+            Failure::Canceled,
+            format!("Can not ask \"{message}\" without a terminal. Use --yes to answer yes."),
+        ));
+    }
+    confirm(message)
+}
+fn default_trick_dependencies() -> BTreeMap<String, Vec<String>> {
+    [
+        ("cjkfonts", vec!["corefonts"]),
+        ("fakechinese", vec!["corefonts"]),
+        ("fakejapanese", vec!["corefonts"]),
+        ("fakekorean", vec!["corefonts"]),
+        ("xna40", vec!["dotnet40"]),
+    ]
+    .into_iter()
+    .map(|(verb, deps)| {
+        (
+            verb.to_string(),
+            deps.into_iter().map(|d| d.to_string()).collect(),
+        )
+    })
+    .collect()
+}
+/// Insert known prerequisites ahead of each winetricks command unless already executed.
+fn resolve_trick_dependencies(
+    tricks: Vec<String>,
+    executed_tricks: &[String],
+    dependencies: &BTreeMap<String, Vec<String>>,
+) -> Vec<String> {
+    fn visit(
+        trick: String,
+        executed_tricks: &[String],
+        dependencies: &BTreeMap<String, Vec<String>>,
+        visiting: &mut HashSet<String>,
+        resolved: &mut Vec<String>,
+    ) {
+        if resolved.contains(&trick) || !visiting.insert(trick.clone()) {
+            return;
+        }
+        let verb = trick.split_whitespace().next().unwrap_or_default();
+        for dependency in dependencies.get(verb).into_iter().flatten() {
+            if !executed_tricks.contains(dependency) {
+                visit(
+                    dependency.clone(),
+                    executed_tricks,
+                    dependencies,
+                    visiting,
+                    resolved,
+                );
+            }
+        }
+        resolved.push(trick);
+    }
+    let mut visiting = HashSet::new();
+    let mut resolved = vec![];
+    for trick in tricks {
+        visit(
+            trick,
+            executed_tricks,
+            dependencies,
+            &mut visiting,
+            &mut resolved,
+        );
+    }
+    resolved
+}
+/// Split comma separated winetricks commands.
+/// A command can have arguments separated by spaces (e.g. "settings fontsmooth=rgb"),
+/// and the whole command with normalized spaces is used as the executed key.
+fn split_tricks(tricks: &[String]) -> Vec<String> {
+    tricks
+        .iter()
+        .flat_map(|trick| trick.split(","))
+        .map(|trick| trick.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|trick| !trick.is_empty())
+        .collect()
+}
+/// Arguments of winetricks for the command, which is split by spaces after the extra arguments.
+fn get_winetricks_args<'a>(winetricks_arg: &'a [String], trick: &'a str) -> Vec<&'a str> {
+    winetricks_arg
+        .iter()
+        .map(|arg| arg.as_str())
+        .chain(trick.split_whitespace())
+        .collect()
+}
+fn exec_command<I, S>(
+    purpose: CommandPurpose,
+    command: impl AsRef<OsStr>,
+    args: I,
+    wine_prefix: impl AsRef<Path>,
+    envs: &BTreeMap<String, String>,
+) -> Result<ExitStatus>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let command = command.as_ref();
+    let wine_prefix = wine_prefix.as_ref();
+    let args = collect_args(args);
+    let started = Instant::now();
+    let status = new_command(command, &args, wine_prefix, envs)
+        .stderr(Stdio::inherit())
+        .status();
+    record_command(
+        purpose,
+        command,
+        &args,
+        wine_prefix,
+        envs,
+        status.as_ref().ok(),
+        started.elapsed(),
+    )?;
+    status.map_err(|e| spawn_error(command, e))
+}
+/// Same as `exec_command` but also returns the last lines of stderr.
+/// stderr is still printed to the console while running.
+/// The command is killed and the status is None when it exceeds the timeout.
+fn exec_command_with_stderr_tail<I, S>(
+    purpose: CommandPurpose,
+    command: impl AsRef<OsStr>,
+    args: I,
+    wine_prefix: impl AsRef<Path>,
+    envs: &BTreeMap<String, String>,
+    timeout: Option<Duration>,
+) -> Result<(Option<ExitStatus>, Vec<String>)>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let command = command.as_ref();
+    let wine_prefix = wine_prefix.as_ref();
+    let args = collect_args(args);
+    let started = Instant::now();
+    let mut child = match new_command(command, &args, wine_prefix, envs)
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            record_command(
+                purpose,
+                command,
+                &args,
+                wine_prefix,
+                envs,
+                None,
+                started.elapsed(),
+            )?;
+            return Err(spawn_error(command, e));
+        }
+    };
+    let tail = Arc::new(Mutex::new(VecDeque::with_capacity(STDERR_TAIL_LINES)));
+    let reader = child.stderr.take().map(|stderr| {
+        let tail = tail.clone();
+        thread::spawn(move || -> io::Result<()> {
+            let mut console = io::stderr();
+            for line in BufReader::new(stderr).split(b'\n') {
+                let line = line?;
+                console.write_all(&line)?;
+                console.write_all(b"\n")?;
+                let mut tail = tail.lock().unwrap_or_else(|e| e.into_inner());
+                if tail.len() == STDERR_TAIL_LINES {
+                    tail.pop_front();
+                }
+                tail.push_back(String::from_utf8_lossy(&line).to_string());
+            }
+            Ok(())
+        })
+    });
+    let status = match timeout {
+        Some(timeout) => loop {
+            if let Some(status) = child.try_wait()? {
+                break Some(status);
+            }
+            if started.elapsed() >= timeout {
+                child.kill()?;
+                child.wait()?;
+                break None;
+            }
+            thread::sleep(Duration::from_millis(100));
+        },
+        None => Some(child.wait()?),
+    };
+    // Processes started by the command may still hold stderr after a timeout.
+    if status.is_some()
+        && let Some(reader) = reader
+    {
+        reader
+            .join()
+            .map_err(|_| anyhow!("Can not read stderr of {}.", command.to_string_lossy()))??;
+    }
+    record_command(
+        purpose,
+        command,
+        &args,
+        wine_prefix,
+        envs,
+        status.as_ref(),
+        started.elapsed(),
+    )?;
+    let tail = tail.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    Ok((status, tail.into()))
+}
+/// Same as `exec_command` but stderr is written to the file, which is rotated by
+/// WINE_TRACE_MAX_BYTES into `<file>.1` and more.
+fn exec_command_with_stderr_file<I, S>(
+    purpose: CommandPurpose,
+    command: impl AsRef<OsStr>,
+    args: I,
+    wine_prefix: impl AsRef<Path>,
+    envs: &BTreeMap<String, String>,
+    stderr_path: &Path,
+) -> Result<ExitStatus>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let command = command.as_ref();
+    let wine_prefix = wine_prefix.as_ref();
+    let args = collect_args(args);
+    let started = Instant::now();
+    let mut child = match new_command(command, &args, wine_prefix, envs)
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            record_command(
+                purpose,
+                command,
+                &args,
+                wine_prefix,
+                envs,
+                None,
+                started.elapsed(),
+            )?;
+            return Err(spawn_error(command, e));
+        }
+    };
+    if let Some(mut stderr) = child.stderr.take() {
+        let mut file = File::create(stderr_path)?;
+        let mut written = 0;
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let len = stderr.read(&mut buf)?;
+            if len == 0 {
+                break;
+            }
+            if written + len as u64 > WINE_TRACE_MAX_BYTES {
+                rotate_file(stderr_path)?;
+                file = File::create(stderr_path)?;
+                written = 0;
+            }
+            file.write_all(&buf[..len])?;
+            written += len as u64;
+        }
+    }
+    let status = child.wait()?;
+    record_command(
+        purpose,
+        command,
+        &args,
+        wine_prefix,
+        envs,
+        Some(&status),
+        started.elapsed(),
+    )?;
+    Ok(status)
+}
+/// Move `<file>` to `<file>.1`, `<file>.1` to `<file>.2` and so on, keeping WINE_TRACE_FILES.
+fn rotate_file(path: &Path) -> Result<()> {
+    let rotated = |i: usize| {
+        let mut rotated = path.as_os_str().to_os_string();
+        rotated.push(format!(".{i}"));
+        PathBuf::from(rotated)
+    };
+    for i in (1..WINE_TRACE_FILES).rev() {
+        let from = if i == 1 {
+            path.to_path_buf()
+        } else {
+            rotated(i - 1)
+        };
+        if from.exists() {
+            fs::rename(from, rotated(i))?;
+        }
+    }
+    Ok(())
+}
+fn collect_args<I, S>(args: I) -> Vec<OsString>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    args.into_iter()
+        .map(|arg| arg.as_ref().to_os_string())
+        .collect()
+}
+/// Start recording every command invocation to `ledger_path` as JSON lines.
+fn set_command_ledger(ledger_path: PathBuf) {
+    let _ = COMMAND_LEDGER.set(ledger_path);
+}
+/// Clear the host environment variables of commands except the ones matching `patterns`.
+fn set_env_passthrough(patterns: Vec<String>) {
+    let _ = ENV_PASSTHROUGH.set(patterns);
+}
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let (pattern, text) = (pattern.as_bytes(), text.as_bytes());
+    let (mut p, mut t) = (0, 0);
+    let mut star = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+/// What a command is run for, which decides the time of the metrics it is counted in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CommandPurpose {
+    Wine,
+    Winetricks,
+    /// wineserver and the commands which manage the prefix, which are not counted.
+    Other,
+}
+fn record_command(
+    purpose: CommandPurpose,
+    command: &OsStr,
+    args: &[OsString],
+    wine_prefix: &Path,
+    envs: &BTreeMap<String, String>,
+    status: Option<&ExitStatus>,
+    duration: Duration,
+) -> Result<()> {
+    with_metrics(|metrics| match purpose {
+        CommandPurpose::Wine => metrics.wine_secs += duration.as_secs_f64(),
+        CommandPurpose::Winetricks => metrics.winetricks_secs += duration.as_secs_f64(),
+        CommandPurpose::Other => {}
+    });
+    let Some(ledger_path) = COMMAND_LEDGER.get() else {
+        return Ok(());
+    };
+    let record = serde_json::json!({
+        "command": command.to_string_lossy(),
+        "args": args.iter().map(|arg| arg.to_string_lossy()).collect::<Vec<_>>(),
+        "wine_prefix": wine_prefix,
+        "envs": envs,
+        "status": status.and_then(|status| status.code()),
+        "duration_secs": duration.as_secs_f64(),
+    });
+    let mut ledger = File::options()
+        .create(true)
+        .append(true)
+        .open(ledger_path)?;
+    writeln!(ledger, "{record}")?;
+    Ok(())
+}
+/// Wrap text with the ANSI color code when stderr is colored.
+fn paint(text: &str, color: &str) -> String {
+    if COLOR.get().copied().unwrap_or(false) {
+        format!("\x1b[{color}m{text}\x1b[0m")
+    } else {
+        text.to_string()
+    }
+}
+fn warn(message: impl fmt::Display) {
+    eprintln!("{} {message}", paint("Warning:", "33"));
+}
+fn spawn_error(command: &OsStr, error: io::Error) -> anyhow::Error {
+    let command = command.to_string_lossy();
+    if error.kind() == io::ErrorKind::NotFound {
+        fail(
+            Failure::MissingProgram,
+            format!("{command} is not found. Please install {command} or check your PATH."),
+        )
+    } else {
+        anyhow!(error).context(format!("Can not run {command}."))
+    }
+}
+fn new_command<I, S>(
+    command: impl AsRef<OsStr>,
+    args: I,
+    wine_prefix: impl AsRef<Path>,
+    envs: &BTreeMap<String, String>,
+) -> Command
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let mut command = if let Some(user) = RUN_AS.get() {
+        // sudo resets the environment except the preserved ones.
+        let preserved = [WINEPREFIX]
+            .into_iter()
+            .chain(DISPLAY_ENVS)
+            .chain(envs.keys().map(|key| key.as_str()))
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut sudo = Command::new("sudo");
+        sudo.args(["-u", user, "-H"])
+            .arg(format!("--preserve-env={preserved}"))
+            .arg("--")
+            .arg(command.as_ref());
+        sudo
+    } else {
+        Command::new(command.as_ref())
+    };
+    if let Some(patterns) = ENV_PASSTHROUGH.get() {
+        command.env_clear().envs(env::vars_os().filter(|(key, _)| {
+            let key = key.to_string_lossy();
+            ALWAYS_PASSED_ENVS.contains(&key.as_ref())
+                || patterns.iter().any(|pattern| glob_match(pattern, &key))
+        }));
+    }
+    if let Some(display) = HEADLESS_DISPLAY.get() {
+        command
+            .env("DISPLAY", display)
+            .env_remove("WAYLAND_DISPLAY");
+    }
+    command
+        .args(args)
+        .stdout(Stdio::inherit())
+        .env(WINEPREFIX, wine_prefix.as_ref().as_os_str())
+        .envs(envs);
+    command
+}
+/// Capture of the screen which is repeated until exe exits, so that the last one shows the
+/// screen just before the exit.
+struct ScreenshotCapture {
+    path: PathBuf,
+    stop: Arc<std::sync::atomic::AtomicBool>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl ScreenshotCapture {
+    fn finish(self) {
+        self.stop.store(true, std::sync::atomic::Ordering::Relaxed);
+        let _ = self.handle.join();
+        if self.path.exists() {
+            println!("Save screenshot {}", self.path.display());
+        } else {
+            warn("Can not capture the screen of exe.");
+        }
+    }
+}
+
+fn start_screenshot_capture(
+    path: PathBuf,
+    envs: &BTreeMap<String, String>,
+) -> Result<ScreenshotCapture> {
+    let display = envs
+        .get("DISPLAY")
+        .cloned()
+        .or_else(|| HEADLESS_DISPLAY.get().cloned())
+        .or_else(|| {
+            env::var("DISPLAY")
+                .ok()
+                .filter(|display| !display.is_empty())
+        });
+    let wayland_display = envs.get("WAYLAND_DISPLAY").cloned().or_else(|| {
+        env::var("WAYLAND_DISPLAY")
+            .ok()
+            .filter(|display| !display.is_empty())
+    });
+    // import decides the format by the extension.
+    let temp_path = path.with_extension("part.png");
+    let mut command = match (display, wayland_display) {
+        (Some(display), _) => {
+            let mut command = Command::new("import");
+            command.args(["-display", &display, "-window", "root"]);
+            command
+        }
+        (None, Some(wayland_display)) => {
+            let mut command = Command::new("grim");
+            command.env("WAYLAND_DISPLAY", wayland_display);
+            command
+        }
+        (None, None) => bail!("There is no display to capture."),
+    };
+    command
+        .arg(&temp_path)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    let program = command.get_program().to_os_string();
+    // the capture does not start when the capture tool is missing or can not capture.
+    let status = command.status().map_err(|e| spawn_error(&program, e))?;
+    if !status.success() {
+        bail!(
+            "Can not capture the screen with {}. {status}",
+            program.to_string_lossy()
+        );
+    }
+    let _ = fs::remove_file(&temp_path);
+    let _ = fs::remove_file(&path);
+    let stop = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let handle = {
+        let path = path.clone();
+        let stop = stop.clone();
+        thread::spawn(move || {
+            while !stop.load(std::sync::atomic::Ordering::Relaxed) {
+                if command.status().is_ok_and(|status| status.success()) {
+                    let _ = fs::rename(&temp_path, &path);
+                }
+                for _ in 0..20 {
+                    if stop.load(std::sync::atomic::Ordering::Relaxed) {
+                        break;
+                    }
+                    thread::sleep(Duration::from_millis(100));
+                }
+            }
+        })
+    };
+    Ok(ScreenshotCapture { path, stop, handle })
+}
+fn set_run_as(user: String) {
+    let _ = RUN_AS.set(user);
+}
+/// Change the owner of the wine prefix to the user, since wine refuses a prefix of another user.
+fn give_wine_prefix(exec_env_path: &Path, user: &str) -> Result<()> {
+    let exec_env_wine_path = exec_env_path.join(".wine");
+    println!("Give {} to user {user}", exec_env_wine_path.display());
+    let command = OsStr::new("sudo");
+    let status = Command::new(command)
+        .args(["chown", "-R", "--", user])
+        .arg(&exec_env_wine_path)
+        .status()
+        .map_err(|e| spawn_error(command, e))?;
+    if !status.success() {
+        bail!("chown is not succeed {status}");
+    }
+    Ok(())
+}
+/// Xvfb which is killed when it is dropped.
+struct Xvfb(std::process::Child);
+
+impl Drop for Xvfb {
+    fn drop(&mut self) {
+        // SIGTERM lets Xvfb remove its lock file and socket.
+        let terminated = i32::try_from(self.0.id()).is_ok_and(|pid| {
+            nix::sys::signal::kill(
+                nix::unistd::Pid::from_raw(pid),
+                nix::sys::signal::Signal::SIGTERM,
+            )
+            .is_ok()
+        });
+        if !terminated {
+            let _ = self.0.kill();
+        }
+        let _ = self.0.wait();
+    }
+}
+
+/// Start Xvfb on a free display and use it for wine and winetricks.
+fn start_xvfb() -> Result<Xvfb> {
+    let number = (99..1000)
+        .find(|number| {
+            !Path::new(&format!("/tmp/.X{number}-lock")).exists()
+                && !Path::new(&format!("/tmp/.X11-unix/X{number}")).exists()
+        })
+        .ok_or_else(|| anyhow!("Can not find a free X display."))?;
+    let display = format!(":{number}");
+    let command = OsStr::new("Xvfb");
+    let child = Command::new(command)
+        .args([&display, "-screen", "0", "1280x1024x24", "-nolisten", "tcp"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| {
+            spawn_error(command, e).context("--headless needs Xvfb. (e.g. the xvfb package)")
+        })?;
+    let mut xvfb = Xvfb(child);
+    let socket = PathBuf::from(format!("/tmp/.X11-unix/X{number}"));
+    let started = Instant::now();
+    while !socket.exists() {
+        if let Some(status) = xvfb.0.try_wait()? {
+            bail!("Xvfb is not succeed {status}");
+        }
+        if started.elapsed() > Duration::from_secs(10) {
+            bail!("Xvfb does not start on display {display}.");
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    println!("Start Xvfb on display {display}");
+    let _ = HEADLESS_DISPLAY.set(display);
+    Ok(xvfb)
+}
+fn steam_dir() -> Option<PathBuf> {
+    let base_dirs = BaseDirs::new()?;
+    [
+        base_dirs.home_dir().join(".steam/steam"),
+        base_dirs.data_dir().join("Steam"),
+    ]
+    .into_iter()
+    .find(|dir| dir.exists())
+}
+fn resolve_proton(proton: &str) -> Result<PathBuf> {
+    let proton_path = Path::new(proton);
+    let candidates = if proton_path.exists() {
+        vec![proton_path.to_path_buf()]
+    } else if let Some(steam_dir) = steam_dir() {
+        vec![
+            steam_dir.join("steamapps/common").join(proton),
+            steam_dir.join("compatibilitytools.d").join(proton),
+        ]
+    } else {
+        vec![]
+    };
+    candidates
+        .into_iter()
+        .map(|path| {
+            if path.is_dir() {
+                path.join("proton")
+            } else {
+                path
+            }
+        })
+        .find(|path| path.is_file())
+        .ok_or_else(|| anyhow!("Can not find proton: {proton}"))
+}
+fn proton_wine_envs(proton: impl AsRef<Path>) -> BTreeMap<String, String> {
+    let mut envs = BTreeMap::new();
+    if let Some(proton_dir) = proton.as_ref().parent() {
+        // newer proton ships wine under files, older one under dist.
+        for dist in ["files", "dist"] {
+            let bin_dir = proton_dir.join(dist).join("bin");
+            if bin_dir.join("wine").exists() {
+                envs.insert(
+                    "WINE".to_string(),
+                    bin_dir.join("wine").to_string_lossy().to_string(),
+                );
+                envs.insert(
+                    "WINESERVER".to_string(),
+                    bin_dir.join("wineserver").to_string_lossy().to_string(),
+                );
+                break;
+            }
+        }
+    }
+    envs
+}
+fn print_prefix_size(exec_env_path: impl AsRef<Path>) -> Result<()> {
+    let exec_env_path = exec_env_path.as_ref();
+    println!(
+        "Prefix {} uses {}",
+        exec_env_path.display(),
+        format_size(dir_size(exec_env_path)?)
+    );
+    Ok(())
+}
+fn dir_size(path: impl AsRef<Path>) -> Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            size += dir_size(entry.path())?;
+        } else if file_type.is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+    Ok(size)
+}
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = size as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{size} {}", UNITS[unit])
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
 }
-
-#[derive(Serialize, Deserialize, Debug)]
-struct ExecEnv {
-    #[serde(default)]
-    executed_tricks: HashSet<String>,
+/// Directory where wineserver makes the sockets of the prefixes of the user.
+/// It is shared with the sandbox so that `wait_wineserver` finds the same wineserver outside.
+fn get_wineserver_dir() -> PathBuf {
+    PathBuf::from(format!("/tmp/.wine-{}", nix::unistd::getuid()))
 }
-
-#[derive(Parser, Debug)]
-#[command(version,about,long_about = None)]
-struct Args {
-    /// Run winetricks commands when it is not yet executed.
-    #[arg(long)]
-    with_tricks: Vec<String>,
-    /// Path to exe file.
-    exec_path: PathBuf,
-    /// Arguments for exe.
-    args: Vec<String>,
+/// bwrap can not bind the wineserver dir before wineserver makes it.
+fn create_wineserver_dir() -> Result<()> {
+    let wineserver_dir = get_wineserver_dir();
+    if !wineserver_dir.exists() {
+        // wineserver refuses the dir which other users can access.
+        fs::DirBuilder::new().mode(0o700).create(&wineserver_dir)?;
+    }
+    Ok(())
 }
-
-fn main() -> Result<()> {
-    run(Args::parse())
+/// Wait for the prefix activity such as registry flush to finish. Failures are only warned
+/// since wine itself is already succeed.
+fn wait_wineserver(exec_conf: &ExecEnv, wine_prefix: &Path, envs: &BTreeMap<String, String>) {
+    match exec_command(
+        CommandPurpose::Other,
+        exec_conf.runner.get().wineserver_command(),
+        ["-w"],
+        wine_prefix,
+        envs,
+    ) {
+        Ok(status) if status.success() => {}
+        Ok(status) => warn(format!("wineserver -w is not succeed {status}")),
+        Err(e) => warn(format!("{e:#}")),
+    }
 }
-
-fn run(args: Args) -> Result<()> {
-    let data_dir = prepare()?;
-    let exec_env_path =
-        if let Some(exec_env_path) = get_base_env_dir_from_exec_path(&args.exec_path, &data_dir) {
-            exec_env_path
-        } else {
-            get_env_dir(&args.exec_path, &data_dir)?
+/// Kill the processes of the prefix. Failures are only warned.
+fn kill_wineserver(exec_conf: &ExecEnv, wine_prefix: &Path, envs: &BTreeMap<String, String>) {
+    match exec_command(
+        CommandPurpose::Other,
+        exec_conf.runner.get().wineserver_command(),
+        ["-k"],
+        wine_prefix,
+        envs,
+    ) {
+        Ok(status) if status.success() => {}
+        Ok(status) => warn(format!("wineserver -k is not succeed {status}")),
+        Err(e) => warn(format!("{e:#}")),
+    }
+}
+/// Runner of exe, which decides the layout of the wine prefix, the wine which winetricks uses
+/// and how exe is launched.
+trait Runner {
+    fn kind(&self) -> RunnerKind;
+    /// Wine prefix under `.wine` of the exec env.
+    fn wine_prefix(&self, exec_env_wine_path: PathBuf) -> PathBuf;
+    fn wine_command(&self) -> OsString;
+    fn wineserver_command(&self) -> String;
+    /// Environment variables which make winetricks use the wine of the runner.
+    fn tricks_envs(&self) -> BTreeMap<String, String>;
+    /// Program and its arguments which launch exe with `wine_args`.
+    fn launch_command(
+        &self,
+        wine_args: Vec<String>,
+        exec_env_wine_path: &Path,
+        envs: &mut BTreeMap<String, String>,
+    ) -> (String, Vec<String>);
+    /// Host directories which the runner reads in the sandbox.
+    fn sandbox_binds(&self) -> Vec<PathBuf>;
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SystemWine;
+impl Runner for SystemWine {
+    fn kind(&self) -> RunnerKind {
+        RunnerKind::Wine
+    }
+    fn wine_prefix(&self, exec_env_wine_path: PathBuf) -> PathBuf {
+        exec_env_wine_path
+    }
+    fn wine_command(&self) -> OsString {
+        OsString::from("wine")
+    }
+    fn wineserver_command(&self) -> String {
+        "wineserver".to_string()
+    }
+    fn tricks_envs(&self) -> BTreeMap<String, String> {
+        BTreeMap::new()
+    }
+    fn launch_command(
+        &self,
+        wine_args: Vec<String>,
+        _exec_env_wine_path: &Path,
+        _envs: &mut BTreeMap<String, String>,
+    ) -> (String, Vec<String>) {
+        ("wine".to_string(), wine_args)
+    }
+    fn sandbox_binds(&self) -> Vec<PathBuf> {
+        vec![]
+    }
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Proton {
+    /// Path to the proton script.
+    script: PathBuf,
+}
+impl Runner for Proton {
+    fn kind(&self) -> RunnerKind {
+        RunnerKind::Proton
+    }
+    // proton creates the wine prefix under the compat data path.
+    fn wine_prefix(&self, exec_env_wine_path: PathBuf) -> PathBuf {
+        exec_env_wine_path.join("pfx")
+    }
+    fn wine_command(&self) -> OsString {
+        proton_wine_envs(&self.script)
+            .remove("WINE")
+            .map(OsString::from)
+            .unwrap_or_else(|| OsString::from("wine"))
+    }
+    fn wineserver_command(&self) -> String {
+        proton_wine_envs(&self.script)
+            .remove("WINESERVER")
+            .unwrap_or_else(|| "wineserver".to_string())
+    }
+    fn tricks_envs(&self) -> BTreeMap<String, String> {
+        proton_wine_envs(&self.script)
+    }
+    fn launch_command(
+        &self,
+        wine_args: Vec<String>,
+        exec_env_wine_path: &Path,
+        envs: &mut BTreeMap<String, String>,
+    ) -> (String, Vec<String>) {
+        envs.insert(
+            STEAM_COMPAT_DATA_PATH.to_string(),
+            exec_env_wine_path.to_string_lossy().to_string(),
+        );
+        if let Some(steam_dir) = steam_dir() {
+            envs.insert(
+                STEAM_COMPAT_CLIENT_INSTALL_PATH.to_string(),
+                steam_dir.to_string_lossy().to_string(),
+            );
+        }
+        let mut proton_args = vec!["run".to_string()];
+        proton_args.extend(wine_args);
+        (self.script.to_string_lossy().to_string(), proton_args)
+    }
+    fn sandbox_binds(&self) -> Vec<PathBuf> {
+        steam_dir().into_iter().collect()
+    }
+}
+/// Runner which a prefix is created for. (--runner)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "Option<PathBuf>", into = "Option<PathBuf>")]
+enum PrefixRunner {
+    Wine(SystemWine),
+    Proton(Proton),
+}
+impl Default for PrefixRunner {
+    fn default() -> Self {
+        PrefixRunner::Wine(SystemWine)
+    }
+}
+impl PrefixRunner {
+    fn get(&self) -> &dyn Runner {
+        match self {
+            PrefixRunner::Wine(runner) => runner,
+            PrefixRunner::Proton(runner) => runner,
+        }
+    }
+}
+impl From<Option<PathBuf>> for PrefixRunner {
+    fn from(proton: Option<PathBuf>) -> Self {
+        match proton {
+            Some(script) => PrefixRunner::Proton(Proton { script }),
+            None => PrefixRunner::Wine(SystemWine),
+        }
+    }
+}
+impl From<PrefixRunner> for Option<PathBuf> {
+    fn from(runner: PrefixRunner) -> Self {
+        match runner {
+            PrefixRunner::Wine(_) => None,
+            PrefixRunner::Proton(proton) => Some(proton.script),
+        }
+    }
+}
+fn parse_toml<T: DeserializeOwned>(data: &[u8], path: impl AsRef<Path>) -> Result<T> {
+    toml::from_slice(data).map_err(|e| {
+        fail(
+            Failure::Config,
+            format!("Can not parse {}: {e}", path.as_ref().display()),
+        )
+    })
+}
+/// Override the fields of config.toml with WINE_ISO_RUN_<FIELD> environment variables.
+/// wine_command_template is split like a shell, and trick_dependencies is a TOML inline table.
+fn apply_env_config(
+    conf: &mut Config,
+    vars: impl IntoIterator<Item = (OsString, OsString)>,
+) -> Result<()> {
+    for (key, value) in vars {
+        // other variables are not read since they can be any bytes.
+        if !key
+            .as_encoded_bytes()
+            .starts_with(ENV_CONFIG_PREFIX.as_bytes())
+        {
+            continue;
+        }
+        let (Some(key), Some(value)) = (key.to_str(), value.to_str()) else {
+            return Err(fail(
+                Failure::Config,
+                format!("{} is not valid UTF-8.", key.display()),
+            ));
         };
-    if !exec_env_path.exists() {
-        fs::create_dir_all(&exec_env_path)?;
+        let field = &key[ENV_CONFIG_PREFIX.len()..];
+        if value.is_empty() {
+            continue;
+        }
+        let invalid =
+            |e: String| fail(Failure::Config, format!("Can not parse {key}={value}: {e}"));
+        match field.to_lowercase().as_str() {
+            "data_dir" => conf.data_dir = Some(PathBuf::from(&value)),
+            "prefixes_dir" => conf.prefixes_dir = Some(PathBuf::from(&value)),
+            "output_dir" => conf.output_dir = Some(PathBuf::from(&value)),
+            "disable_esync" => {
+                conf.disable_esync = match value.to_lowercase().as_str() {
+                    "1" | "true" | "yes" => true,
+                    "0" | "false" | "no" => false,
+                    _ => return Err(invalid("it must be true or false".to_string())),
+                }
+            }
+            "min_free_space_mib" => {
+                conf.min_free_space_mib = value.parse().map_err(|e| invalid(format!("{e}")))?
+            }
+            "arch_default" => {
+                conf.arch_default =
+                    Some(<WineArch as clap::ValueEnum>::from_str(value, true).map_err(invalid)?)
+            }
+            "wine_command_template" => {
+                conf.wine_command_template = split_args(value).map_err(invalid)?
+            }
+            "trick_dependencies" => {
+                conf.trick_dependencies =
+                    toml::from_str::<BTreeMap<String, _>>(&format!("trick_dependencies = {value}"))
+                        .map_err(|e| invalid(e.to_string()))?
+                        .remove("trick_dependencies")
+                        .unwrap_or_default()
+            }
+            _ => warn(format!("{key} is not a field of config.toml.")),
+        }
     }
-
-    let exec_env_conf_path = exec_env_path.join("conf.toml");
-    let mut exec_env_conf_buf = vec![];
-    {
-        let mut exec_env_conf_file = if exec_env_conf_path.exists() {
-            File::open(&exec_env_conf_path)?
-        } else {
-            File::create_new(&exec_env_conf_path)?
+    Ok(())
+}
+fn save_exec_env(exec_env_conf_path: impl AsRef<Path>, exec_env: &ExecEnv) -> Result<()> {
+    fs::write(
+        exec_env_conf_path,
+        toml::to_string_pretty(exec_env)?.as_bytes(),
+    )?;
+    Ok(())
+}
+fn parse_dll_override(s: &str) -> Result<(String, String), String> {
+    let (dll, mode) = s
+        .split_once('=')
+        .ok_or_else(|| format!("dll override must be <dll>=<mode>: {s}"))?;
+    if dll.is_empty() || dll.contains([';', ',']) {
+        return Err(format!("invalid dll name: {dll}"));
+    }
+    let mut kinds = vec![];
+    for kind in mode.split(',') {
+        let kind = match kind {
+            "n" | "native" => "n",
+            "b" | "builtin" => "b",
+            "" | "d" | "disabled" => "",
+            _ => return Err(format!("invalid dll mode: {kind}")),
         };
-        exec_env_conf_file.read_to_end(&mut exec_env_conf_buf)?;
+        if kinds.contains(&kind) {
+            return Err(format!("duplicated dll mode: {mode}"));
+        }
+        kinds.push(kind);
     }
-    let mut exec_conf = toml::from_slice::<ExecEnv>(&exec_env_conf_buf)?;
-    let exec_env_wine_path = exec_env_path.join(".wine");
-    if !exec_env_wine_path.exists() {
-        fs::create_dir_all(&exec_env_wine_path)?;
+    if kinds.contains(&"") && kinds.len() > 1 {
+        return Err(format!(
+            "disabled can not be combined with other modes: {mode}"
+        ));
     }
-
-    println!("Resolve winetricks...");
-    for trick in args.with_tricks {
-        for trick in trick.split(",") {
-            if !exec_conf.executed_tricks.contains(trick) {
-                exec_conf.executed_tricks.insert(trick.to_string());
-                let status = exec_command("winetricks", &[trick.to_string()], &exec_env_wine_path)?;
-                if !status.success() {
-                    bail!("winetricks is not succeed {trick}, status:{status}");
-                }
-                fs::write(
-                    &exec_env_conf_path,
-                    toml::to_string_pretty(&exec_conf)?.as_bytes(),
-                )?;
+    Ok((dll.to_string(), kinds.join(",")))
+}
+fn is_windows_path(path: &str) -> bool {
+    let mut chars = path.chars();
+    path.contains('\\')
+        || (chars.next().is_some_and(|c| c.is_ascii_alphabetic()) && chars.next() == Some(':'))
+}
+fn replace_path_sep(path: PathBuf) -> PathBuf {
+    let Some(path_str) = path.to_str() else {
+        return path;
+    };
+    let mut chars = path_str.chars();
+    let has_drive =
+        chars.next().is_some_and(|c| c.is_ascii_alphabetic()) && chars.next() == Some(':');
+    if !path_str.contains('\\') || has_drive || path.exists() {
+        return path;
+    }
+    PathBuf::from(path_str.replace('\\', "/"))
+}
+/// Translate a host path to the drive of the prefix. drive_c is C: and the others are under Z:.
+fn to_windows_path(path: &str, wine_prefix: impl AsRef<Path>) -> String {
+    if is_windows_path(path) {
+        return path.to_string();
+    }
+    let path = Path::new(path);
+    let (drive, path) = match path.strip_prefix(wine_prefix.as_ref().join("drive_c")) {
+        Ok(path) => ("C:", path),
+        Err(_) => ("Z:", path.strip_prefix("/").unwrap_or(path)),
+    };
+    let components = path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    format!("{drive}\\{}", components.join("\\"))
+}
+/// Translate a windows path to the host path in the prefix. Relative host paths are under drive_c.
+fn from_windows_path(path: &str, wine_prefix: impl AsRef<Path>) -> PathBuf {
+    let wine_prefix = wine_prefix.as_ref();
+    if !is_windows_path(path) {
+        return wine_prefix.join("drive_c").join(path);
+    }
+    let (drive, rest) = match path.split_once(':') {
+        Some((drive, rest)) if drive.len() == 1 => (drive.to_ascii_lowercase(), rest),
+        _ => ("c".to_string(), path),
+    };
+    let mut host_path = match drive.as_str() {
+        "c" => wine_prefix.join("drive_c"),
+        "z" => PathBuf::from("/"),
+        _ => wine_prefix.join("dosdevices").join(format!("{drive}:")),
+    };
+    host_path.extend(rest.split(['\\', '/']).filter(|c| !c.is_empty()));
+    host_path
+}
+fn parse_sha256(s: &str) -> Result<String, String> {
+    if s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(s.to_ascii_lowercase())
+    } else {
+        Err(format!("invalid sha256: {s}"))
+    }
+}
+/// Download exe with curl into the download cache of the data dir, resuming a partial download.
+/// The cached file is reused while it matches `sha256`.
+fn download_exe(url: &str, data_dir: impl AsRef<Path>, sha256: Option<&str>) -> Result<PathBuf> {
+    let download_path = get_download_path(url, data_dir)?;
+    let download_dir = download_path
+        .parent()
+        .ok_or_else(|| anyhow!("Can not get parent of {}.", download_path.display()))?;
+    let file_name = download_path
+        .file_name()
+        .ok_or_else(|| anyhow!("Can not get file name."))?
+        .to_string_lossy();
+    if download_path.exists() {
+        match sha256 {
+            Some(sha256) if file_sha256(&download_path)? != sha256 => {
+                println!(
+                    "{} does not match sha256, download again.",
+                    download_path.display()
+                );
+                fs::remove_file(&download_path)?;
             }
+            _ => return Ok(download_path),
         }
     }
-    let exec_path_str = args.exec_path.to_string_lossy().to_string();
-    let mut wine_args = vec![exec_path_str.clone()];
-    wine_args.extend_from_slice(&args.args);
-    println!("Run wine {exec_path_str}");
-    let status = exec_command("wine", wine_args, exec_env_wine_path)?;
+    fs::create_dir_all(download_dir)?;
+    let part_path = download_dir.join(format!("{file_name}.part"));
+    println!("Download {url}");
+    let command = OsStr::new("curl");
+    let status = Command::new(command)
+        .args([
+            "--fail",
+            "--location",
+            "--continue-at",
+            "-",
+            "--progress-bar",
+        ])
+        .arg("--output")
+        .arg(&part_path)
+        .arg(url)
+        .status()
+        .map_err(|e| spawn_error(command, e))?;
     if !status.success() {
-        bail!("wine is not succeed {status}");
+        bail!("curl is not succeed {status}");
+    }
+    fs::rename(&part_path, &download_path)?;
+    if let Some(sha256) = sha256 {
+        verify_sha256(&download_path, sha256)?;
+    }
+    Ok(download_path)
+}
+/// Path in the download cache where exe of URL is downloaded to.
+fn get_download_path(url: &str, data_dir: impl AsRef<Path>) -> Result<PathBuf> {
+    let file_name = url
+        .split(['?', '#'])
+        .next()
+        .and_then(|url| url.rsplit('/').next())
+        .filter(|file_name| !file_name.is_empty())
+        .ok_or_else(|| anyhow!("Can not get file name from {url}."))?;
+    // urls are kept apart by the hash and the file name is kept for the prefix.
+    Ok(data_dir
+        .as_ref()
+        .join("downloads")
+        .join(&sha256_hex(url.as_bytes())[..16])
+        .join(file_name))
+}
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+fn file_sha256(path: impl AsRef<Path>) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let len = file.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        hasher.update(&buf[..len]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+fn verify_sha256(exec_path: impl AsRef<Path>, sha256: &str) -> Result<()> {
+    let exec_path = exec_path.as_ref();
+    let actual = file_sha256(exec_path)?;
+    if actual != sha256 {
+        bail!(
+            "sha256 of {} does not match. expected:{sha256}, actual:{actual}",
+            exec_path.display()
+        );
     }
-
     Ok(())
 }
-fn exec_command<I, S>(
-    command: impl AsRef<str>,
-    args: I,
-    wine_prefix: impl AsRef<Path>,
-) -> Result<ExitStatus>
-where
-    I: IntoIterator<Item = S>,
-    S: AsRef<OsStr>,
-{
-    Ok(Command::new(command.as_ref())
-        .args(args)
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .env(WINEPREFIX, wine_prefix.as_ref().as_os_str())
-        .status()?)
+fn parse_gpu(s: &str) -> Result<Gpu, String> {
+    match s {
+        "" => Err("gpu must not be empty".to_string()),
+        "discrete" => Ok(Gpu::Discrete),
+        "integrated" => Ok(Gpu::Integrated),
+        _ => Ok(Gpu::Device(s.to_string())),
+    }
+}
+fn is_nvidia_driver_loaded() -> bool {
+    Path::new("/proc/driver/nvidia").exists() || Path::new("/sys/module/nvidia").exists()
+}
+/// Environment variables to select GPU on hybrid graphics. They can be overridden with --env.
+fn get_gpu_envs(gpu: &Gpu) -> BTreeMap<String, String> {
+    let envs: Vec<(&str, &str)> = match gpu {
+        Gpu::Discrete if is_nvidia_driver_loaded() => vec![
+            ("__NV_PRIME_RENDER_OFFLOAD", "1"),
+            ("__GLX_VENDOR_LIBRARY_NAME", "nvidia"),
+            ("__VK_LAYER_NV_optimus", "NVIDIA_only"),
+        ],
+        Gpu::Discrete => vec![("DRI_PRIME", "1")],
+        Gpu::Integrated if is_nvidia_driver_loaded() => vec![
+            ("DRI_PRIME", "0"),
+            ("__VK_LAYER_NV_optimus", "non_NVIDIA_only"),
+        ],
+        Gpu::Integrated => vec![("DRI_PRIME", "0")],
+        Gpu::Device(device) => vec![
+            ("DRI_PRIME", device.as_str()),
+            ("MESA_VK_DEVICE_SELECT", device.as_str()),
+        ],
+    };
+    envs.into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+fn parse_env(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("environment variable must be <KEY>=<VALUE>: {s}"))?;
+    if key.is_empty()
+        || key.starts_with(|c: char| c.is_ascii_digit())
+        || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(format!("invalid environment variable name: {key}"));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+/// Load `KEY=VALUE` lines. Empty lines and lines starting with `#` are ignored.
+fn load_env_file(env_file: impl AsRef<Path>) -> Result<Vec<(String, String)>> {
+    let env_file = env_file.as_ref();
+    let content = fs::read_to_string(env_file)
+        .map_err(|e| anyhow!(e).context(format!("Can not read {}", env_file.display())))?;
+    let mut envs = vec![];
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = parse_env(line).map_err(|e| {
+            fail(
+                Failure::Config,
+                format!("{}:{}: {e}", env_file.display(), i + 1),
+            )
+        })?;
+        let value = value.trim();
+        let value = [('"', '"'), ('\'', '\'')]
+            .iter()
+            .find_map(|(start, end)| value.strip_prefix(*start)?.strip_suffix(*end))
+            .unwrap_or(value);
+        envs.push((key, value.to_string()));
+    }
+    Ok(envs)
 }
-fn get_env_dir(exec_path: impl AsRef<Path>, data_dir: impl AsRef<Path>) -> Result<PathBuf> {
+fn load_args_file(args_file: impl AsRef<Path>) -> Result<Vec<String>> {
+    let args_file = args_file.as_ref();
+    let content = fs::read_to_string(args_file)
+        .map_err(|e| anyhow!(e).context(format!("Can not read {}", args_file.display())))?;
+    split_args(&content).map_err(|e| fail(Failure::Config, format!("{}: {e}", args_file.display())))
+}
+/// Split arguments with the quoting rules of POSIX shells without expansions.
+fn split_args(content: &str) -> Result<Vec<String>, String> {
+    let mut args = vec![];
+    let mut arg: Option<String> = None;
+    let mut chars = content.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '#' if arg.is_none() => {
+                chars.by_ref().find(|c| *c == '\n');
+            }
+            '\'' => {
+                let arg = arg.get_or_insert_default();
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => arg.push(c),
+                        None => return Err("single quote is not closed".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                let arg = arg.get_or_insert_default();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => arg.push(c),
+                            Some(c) => {
+                                arg.push('\\');
+                                arg.push(c);
+                            }
+                            None => return Err("double quote is not closed".to_string()),
+                        },
+                        Some(c) => arg.push(c),
+                        None => return Err("double quote is not closed".to_string()),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                // a backslash at the end of line continues the argument on the next line.
+                Some('\n') => {}
+                Some(c) => arg.get_or_insert_default().push(c),
+                None => return Err("backslash is at the end".to_string()),
+            },
+            c if c.is_whitespace() => args.extend(arg.take()),
+            c => arg.get_or_insert_default().push(c),
+        }
+    }
+    args.extend(arg);
+    Ok(args)
+}
+fn dll_overrides_env(dll_overrides: &BTreeMap<String, String>) -> String {
+    dll_overrides
+        .iter()
+        .map(|(dll, mode)| format!("{dll}={mode}"))
+        .collect::<Vec<_>>()
+        .join(";")
+}
+fn get_env_dir(exec_path: impl AsRef<Path>, prefixes_dir: impl AsRef<Path>) -> Result<PathBuf> {
     let exec_path = exec_path.as_ref();
-    let data_dir = data_dir.as_ref();
-    Ok(data_dir.join(
+    let prefixes_dir = prefixes_dir.as_ref();
+    Ok(prefixes_dir.join(
         exec_path
             .file_name()
             .map(|n| n.to_string_lossy())
@@ -124,30 +5244,34 @@ fn get_env_dir(exec_path: impl AsRef<Path>, data_dir: impl AsRef<Path>) -> Resul
     ))
 }
 
+/// Dirs which contain exec envs, which are prefixes_dir and the named and default prefix dirs.
+fn get_env_roots(
+    data_dir: impl AsRef<Path>,
+    prefixes_dir: impl AsRef<Path>,
+) -> Result<[PathBuf; 3]> {
+    let data_dir = path::absolute(data_dir.as_ref())?;
+    Ok([
+        path::absolute(prefixes_dir.as_ref())?,
+        data_dir.join("named"),
+        data_dir.join("default-prefix"),
+    ])
+}
+/// Exec env dir whose `.wine` contains exe, when the dir is under one of `get_env_roots`.
 fn get_base_env_dir_from_exec_path(
     exec_path: impl AsRef<Path>,
     data_dir: impl AsRef<Path>,
+    prefixes_dir: impl AsRef<Path>,
 ) -> Option<PathBuf> {
-    let exec_path = exec_path.as_ref();
-    let data_dir = data_dir.as_ref();
-    let mut base_wine_prefix_dir = None;
-    let mut taget_path = exec_path;
-    while let Some(parent_dir) = taget_path.parent() {
-        let name = parent_dir
-            .file_name()
-            .map(|n| n.to_str().unwrap_or(""))
-            .unwrap_or("");
-        if name == ".wine" {
-            base_wine_prefix_dir = Some(parent_dir);
-            break;
-        }
-        taget_path = parent_dir;
-    }
-    if let Some(base_wine_prefix_dir) = base_wine_prefix_dir
-        && base_wine_prefix_dir
-            .to_string_lossy()
-            .contains(data_dir.to_string_lossy().as_ref())
-        && let Some(base_env_dir) = base_wine_prefix_dir.parent()
+    let exec_path = path::absolute(exec_path.as_ref()).ok()?;
+    let env_roots = get_env_roots(data_dir, prefixes_dir).ok()?;
+    let base_wine_prefix_dir = exec_path
+        .ancestors()
+        .skip(1)
+        .find(|dir| dir.file_name().is_some_and(|name| name == ".wine"))?;
+    let base_env_dir = base_wine_prefix_dir.parent()?;
+    if env_roots
+        .iter()
+        .any(|env_root| base_wine_prefix_dir.starts_with(env_root))
         && base_env_dir.join("conf.toml").exists()
     {
         Some(base_env_dir.to_path_buf())
@@ -155,33 +5279,875 @@ fn get_base_env_dir_from_exec_path(
         None
     }
 }
-fn prepare() -> Result<PathBuf> {
+fn default_min_free_space_mib() -> u64 {
+    4096
+}
+/// Warn, or fail when `strict`, if the free space of prefixes_dir is lower than the threshold.
+fn check_free_space(prefixes_dir: impl AsRef<Path>, conf: &Config, strict: bool) -> Result<()> {
+    let prefixes_dir = prefixes_dir.as_ref();
+    let stat = nix::sys::statvfs::statvfs(prefixes_dir)?;
+    let free_space = stat.blocks_available() as u64 * stat.fragment_size() as u64;
+    let min_free_space = conf.min_free_space_mib * 1024 * 1024;
+    if free_space < min_free_space {
+        let message = format!(
+            "{} is free on {}, which is lower than {}. A new prefix can take several GB of disk space.",
+            format_size(free_space),
+            prefixes_dir.display(),
+            format_size(min_free_space)
+        );
+        if strict {
+            bail!(message);
+        }
+        warn(message);
+    }
+    Ok(())
+}
+/// Directory for logs of the prefix. Under output_dir, each prefix has the directory of its name.
+fn get_output_dir(
+    exec_env_path: impl AsRef<Path>,
+    conf: &Config,
+    tricks_args: &TricksArgs,
+) -> Result<PathBuf> {
+    let exec_env_path = exec_env_path.as_ref();
+    let Some(output_dir) = tricks_args.output_dir.as_ref().or(conf.output_dir.as_ref()) else {
+        return Ok(exec_env_path.to_path_buf());
+    };
+    let output_dir = output_dir.join(
+        exec_env_path
+            .file_name()
+            .ok_or_else(|| anyhow!("Can not get file name."))?,
+    );
+    if !output_dir.exists() {
+        fs::create_dir_all(&output_dir)?;
+    }
+    Ok(output_dir)
+}
+fn get_prefixes_dir(data_dir: impl AsRef<Path>, conf: &Config) -> Result<PathBuf> {
+    let prefixes_dir = conf
+        .prefixes_dir
+        .clone()
+        .unwrap_or_else(|| data_dir.as_ref().to_path_buf());
+    if !prefixes_dir.exists() {
+        fs::create_dir_all(&prefixes_dir)?;
+    }
+    Ok(prefixes_dir)
+}
+fn prepare() -> Result<(PathBuf, Config)> {
     if let Some(project_dirs) = ProjectDirs::from("", "", APP_NAME) {
         if !project_dirs.data_dir().exists() {
             fs::create_dir_all(project_dirs.data_dir())?;
         }
         let conf_path = project_dirs.data_dir().join("config.toml");
         let mut conf_data = vec![];
-        {
-            let mut conf_file = if !conf_path.exists() {
-                File::create_new(&conf_path)?
-            } else {
-                File::open(&conf_path)?
-            };
-            conf_file.read_to_end(&mut conf_data)?;
+        if conf_path.exists() {
+            File::open(&conf_path)?.read_to_end(&mut conf_data)?;
         }
-        let mut conf = toml::from_slice::<Config>(&conf_data)?;
+        let mut conf = parse_toml::<Config>(&conf_data, &conf_path)?;
         if conf.data_dir.is_none() {
             conf.data_dir = Some(project_dirs.data_local_dir().to_path_buf());
             let save_data = toml::to_string_pretty(&conf)?;
-            fs::write(&conf_path, save_data.as_bytes())?;
+            // the default data dir is used without saving it when config.toml is read-only.
+            if let Err(e) = fs::write(&conf_path, save_data.as_bytes()) {
+                warn(format!(
+                    "Can not write {}: {e}. The default data dir is used.",
+                    conf_path.display()
+                ));
+            }
         }
-        let data_dir = conf.data_dir.unwrap();
+        apply_env_config(&mut conf, env::vars_os())?;
+        let data_dir = conf.data_dir.clone().unwrap();
         if !data_dir.exists() {
             fs::create_dir_all(&data_dir)?;
         }
-        Ok(data_dir)
+        Ok((data_dir, conf))
     } else {
         Err(anyhow!("Can not create project dir."))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Empty directory for the test under the temporary dir.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("{APP_NAME}-test-{name}-{}", std::process::id()));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn missing_program_is_reported_with_exit_code() {
+        let command = OsStr::new("wine-iso-run-missing-program");
+        let error = Command::new(command)
+            .status()
+            .map_err(|e| spawn_error(command, e))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "wine-iso-run-missing-program is not found. \
+             Please install wine-iso-run-missing-program or check your PATH."
+        );
+        assert_eq!(error_exit_code(&error), 5);
+    }
+
+    #[test]
+    fn split_tricks_normalizes_keys() {
+        let tricks = split_tricks(&[
+            "corefonts,vcrun2019".to_string(),
+            " settings   fontsmooth=rgb ,,".to_string(),
+            "prefix=".to_string(),
+        ]);
+        assert_eq!(
+            tricks,
+            [
+                "corefonts",
+                "vcrun2019",
+                "settings fontsmooth=rgb",
+                "prefix="
+            ]
+        );
+        // the same command written with other spaces is the same executed key.
+        assert_eq!(
+            split_tricks(&["settings  fontsmooth=rgb".to_string()]),
+            ["settings fontsmooth=rgb"]
+        );
+    }
+
+    #[test]
+    fn winetricks_args_of_simple_and_argumented_commands() {
+        let winetricks_arg = ["-q".to_string()];
+        assert_eq!(
+            get_winetricks_args(&winetricks_arg, "corefonts"),
+            ["-q", "corefonts"]
+        );
+        assert_eq!(
+            get_winetricks_args(&winetricks_arg, "settings fontsmooth=rgb"),
+            ["-q", "settings", "fontsmooth=rgb"]
+        );
+        assert_eq!(get_winetricks_args(&[], "prefix="), ["prefix="]);
+    }
+
+    #[test]
+    fn concurrent_creations_keep_both_prefixes() {
+        let prefixes_dir = test_dir("concurrent-creations");
+        let barrier = Arc::new(std::sync::Barrier::new(2));
+        let handles = ["a.exe", "b.exe"].map(|name| {
+            let exec_env_path = prefixes_dir.join(name);
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                create_exec_env_dir(&exec_env_path).unwrap()
+            })
+        });
+        for handle in handles {
+            assert!(handle.join().unwrap());
+        }
+        for name in ["a.exe", "b.exe"] {
+            assert!(prefixes_dir.join(name).join("conf.toml").is_file());
+            assert!(prefixes_dir.join(name).join(".wine").is_dir());
+        }
+        fs::remove_dir_all(&prefixes_dir).unwrap();
+    }
+
+    #[test]
+    fn split_args_with_quotes_and_escapes() {
+        assert_eq!(
+            split_args("-w 1280 'My Documents' \"a \\\"b\\\" \\n\"").unwrap(),
+            ["-w", "1280", "My Documents", "a \"b\" \\n"]
+        );
+        assert_eq!(
+            split_args("# comment\n--name=foo' 'bar a\\ b \\'c\\\n d").unwrap(),
+            ["--name=foo bar", "a b", "'c", "d"]
+        );
+        assert_eq!(split_args("'' \"\"").unwrap(), ["", ""]);
+        assert_eq!(
+            split_args("--flag # comment\n'a\\b'").unwrap(),
+            ["--flag", "a\\b"]
+        );
+        assert!(split_args("'a").is_err());
+        assert!(split_args("\"a").is_err());
+        assert!(split_args("a\\").is_err());
+    }
+
+    #[test]
+    fn creation_after_interrupted_creation() {
+        let prefixes_dir = test_dir("interrupted-creation");
+        let exec_env_path = prefixes_dir.join("game.exe");
+        // the process was killed before conf.toml was written and the dir was renamed.
+        let temp_path = prefixes_dir
+            .join(CREATING_EXEC_ENV_DIR)
+            .join(format!("game.exe.{}", std::process::id()));
+        fs::create_dir_all(temp_path.join(".wine/drive_c")).unwrap();
+        assert!(!exec_env_path.exists());
+
+        assert!(create_exec_env_dir(&exec_env_path).unwrap());
+        assert!(exec_env_path.join("conf.toml").is_file());
+        assert!(!exec_env_path.join(".wine/drive_c").exists());
+        assert!(!prefixes_dir.join(CREATING_EXEC_ENV_DIR).exists());
+        // the created prefix is kept.
+        assert!(!create_exec_env_dir(&exec_env_path).unwrap());
+        fs::remove_dir_all(&prefixes_dir).unwrap();
+    }
+
+    /// Minimal PE exe which has the optional header of the magic and no resources.
+    fn write_pe(path: &Path, magic: u16) {
+        fs::write(path, pe_data(magic)).unwrap();
+    }
+    fn pe_data(magic: u16) -> Vec<u8> {
+        let mut exe = vec![0; 0x200];
+        exe[..2].copy_from_slice(b"MZ");
+        exe[0x3c..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+        exe[0x80..0x84].copy_from_slice(b"PE\0\0");
+        let machine: u16 = if magic == 0x20b { 0x8664 } else { 0x14c };
+        exe[0x84..0x86].copy_from_slice(&machine.to_le_bytes());
+        exe[0x98..0x9a].copy_from_slice(&magic.to_le_bytes());
+        exe
+    }
+    fn put_u32(data: &mut [u8], offset: usize, value: u32) {
+        data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+    /// 64-bit PE exe with a section at 0x200 of the file which has the manifest resource.
+    fn pe_with_manifest(manifest: &str) -> Vec<u8> {
+        let mut exe = pe_data(0x20b);
+        // a section and the optional header of 240 bytes, where 16 data directories are.
+        exe[0x86..0x88].copy_from_slice(&1u16.to_le_bytes());
+        exe[0x94..0x96].copy_from_slice(&240u16.to_le_bytes());
+        put_u32(&mut exe, 0x104, 16);
+        put_u32(&mut exe, 0x118, 0x1000);
+        let section = 0x98 + 240;
+        put_u32(&mut exe, section + 8, 0x200);
+        put_u32(&mut exe, section + 12, 0x1000);
+        put_u32(&mut exe, section + 16, 0x200);
+        put_u32(&mut exe, section + 20, 0x200);
+        // type 24, name 1 and language 0x409 directories and the data entry of the manifest.
+        let mut resource = vec![0; 0x60];
+        for (entry, id, offset) in [
+            (0, 24, 0x80000018),
+            (0x18, 1, 0x80000030),
+            (0x30, 0x409, 0x48),
+        ] {
+            resource[entry + 14..entry + 16].copy_from_slice(&1u16.to_le_bytes());
+            put_u32(&mut resource, entry + 16, id);
+            put_u32(&mut resource, entry + 20, offset);
+        }
+        put_u32(&mut resource, 0x48, 0x1060);
+        put_u32(&mut resource, 0x4c, manifest.len() as u32);
+        resource.extend(manifest.as_bytes());
+        resource.resize(0x200, 0);
+        exe.extend(resource);
+        exe
+    }
+
+    #[test]
+    fn prefix_arch_precedence() {
+        let dir = test_dir("prefix-arch");
+        let exe32 = dir.join("x32.exe");
+        let exe64 = dir.join("x64.exe");
+        write_pe(&exe32, 0x10b);
+        write_pe(&exe64, 0x20b);
+        let mut conf = parse_toml::<Config>(b"", "config.toml").unwrap();
+        let arch = |arch: Option<WineArch>, exec_path: &Path, conf: &Config| {
+            let mut exec_conf = ExecEnv::default();
+            init_prefix_arch(&mut exec_conf, arch, exec_path, conf).unwrap();
+            exec_conf.arch
+        };
+
+        // wine decides it without --arch, detected bitness and config default.
+        assert_eq!(arch(None, &exe32, &conf), None);
+        assert_eq!(arch(None, &exe64, &conf), Some(WineArch::Win64));
+        conf.arch_default = Some(WineArch::Win32);
+        // --arch > detected bitness > config default.
+        assert_eq!(arch(None, &exe32, &conf), Some(WineArch::Win32));
+        assert_eq!(arch(None, &exe64, &conf), Some(WineArch::Win64));
+        assert_eq!(
+            arch(Some(WineArch::Win32), &exe64, &conf),
+            Some(WineArch::Win32)
+        );
+        conf.arch_default = Some(WineArch::Win64);
+        assert_eq!(arch(None, &exe32, &conf), Some(WineArch::Win64));
+        assert_eq!(
+            arch(Some(WineArch::Win32), &exe32, &conf),
+            Some(WineArch::Win32)
+        );
+        // proton decides it by itself.
+        let mut exec_conf = ExecEnv {
+            runner: PrefixRunner::Proton(Proton {
+                script: PathBuf::from("/usr/bin/proton"),
+            }),
+            ..Default::default()
+        };
+        assert!(!init_prefix_arch(&mut exec_conf, Some(WineArch::Win32), &exe64, &conf).unwrap());
+        assert_eq!(exec_conf.arch, None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn flags_after_separator_are_passed_to_exe() {
+        let cli = Cli::try_parse_from([
+            APP_NAME,
+            "--sandbox",
+            "game.exe",
+            "--",
+            "--fullscreen",
+            "-w",
+        ])
+        .unwrap();
+        assert!(cli.command.is_none());
+        assert!(cli.run.sandbox);
+        assert_eq!(cli.run.exec_path, Some(PathBuf::from("game.exe")));
+        assert_eq!(cli.run.args, ["--fullscreen", "-w"]);
+
+        // arguments after the first one are passed too.
+        let cli =
+            Cli::try_parse_from([APP_NAME, "game.exe", "/nosound", "-w", "--sandbox"]).unwrap();
+        assert!(!cli.run.sandbox);
+        assert_eq!(cli.run.args, ["/nosound", "-w", "--sandbox"]);
+    }
+
+    #[test]
+    fn config_precedence() {
+        let vars = |vars: &[(&str, &str)]| {
+            vars.iter()
+                .map(|(key, value)| (OsString::from(key), OsString::from(value)))
+                .collect::<Vec<_>>()
+        };
+        // built-in default.
+        let mut conf = parse_toml::<Config>(b"", "config.toml").unwrap();
+        apply_env_config(&mut conf, vars(&[("HOME", "/home/user")])).unwrap();
+        assert_eq!(conf.min_free_space_mib, 4096);
+        assert_eq!(conf.arch_default, None);
+        // config.toml > built-in default.
+        let toml = b"min_free_space_mib = 100\narch_default = \"win64\"\n";
+        let mut conf = parse_toml::<Config>(toml, "config.toml").unwrap();
+        apply_env_config(&mut conf, vars(&[("WINE_ISO_RUN_ARCH_DEFAULT", "")])).unwrap();
+        assert_eq!(conf.min_free_space_mib, 100);
+        assert_eq!(conf.arch_default, Some(WineArch::Win64));
+        // env var > config.toml.
+        apply_env_config(
+            &mut conf,
+            vars(&[
+                ("WINE_ISO_RUN_ARCH_DEFAULT", "win32"),
+                ("WINE_ISO_RUN_DISABLE_ESYNC", "yes"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(conf.min_free_space_mib, 100);
+        assert_eq!(conf.arch_default, Some(WineArch::Win32));
+        assert!(conf.disable_esync);
+        // command line option > env var.
+        let mut exec_conf = ExecEnv::default();
+        init_prefix_arch(
+            &mut exec_conf,
+            Some(WineArch::Win64),
+            Path::new("missing.exe"),
+            &conf,
+        )
+        .unwrap();
+        assert_eq!(exec_conf.arch, Some(WineArch::Win64));
+
+        let error = apply_env_config(
+            &mut conf,
+            vars(&[("WINE_ISO_RUN_MIN_FREE_SPACE_MIB", "many")]),
+        )
+        .unwrap_err();
+        assert_eq!(error_exit_code(&error), 6);
+    }
+
+    #[test]
+    fn replace_path_sep_of_windows_paths() {
+        let replace = |path: &str| replace_path_sep(PathBuf::from(path));
+        assert_eq!(replace(r"App\game.exe"), PathBuf::from("App/game.exe"));
+        assert_eq!(
+            replace(r"games/App\bin\game.exe"),
+            PathBuf::from("games/App/bin/game.exe")
+        );
+        assert_eq!(
+            replace(r"\\App\\game.exe"),
+            PathBuf::from("//App//game.exe")
+        );
+        assert_eq!(replace("App/game.exe"), PathBuf::from("App/game.exe"));
+        // paths with a drive letter are translated by wine.
+        assert_eq!(
+            replace(r"C:\Games\game.exe"),
+            PathBuf::from(r"C:\Games\game.exe")
+        );
+        assert_eq!(
+            replace(r"z:/App\game.exe"),
+            PathBuf::from(r"z:/App\game.exe")
+        );
+        // a file name which contains a backslash is kept when it exists.
+        let dir = test_dir("replace-path-sep");
+        let exec_path = dir.join(r"App\game.exe");
+        fs::write(&exec_path, b"").unwrap();
+        assert_eq!(replace_path_sep(exec_path.clone()), exec_path);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn config_of_non_utf8_env_vars() {
+        use std::os::unix::ffi::OsStringExt;
+        let non_utf8 = || OsString::from_vec(vec![b'a', 0xff]);
+        let mut conf = parse_toml::<Config>(b"", "config.toml").unwrap();
+        // unrelated variables are ignored whatever they contain.
+        apply_env_config(
+            &mut conf,
+            [
+                (non_utf8(), non_utf8()),
+                (OsString::from("LESSOPEN"), non_utf8()),
+            ],
+        )
+        .unwrap();
+        let error = apply_env_config(
+            &mut conf,
+            [(OsString::from("WINE_ISO_RUN_DATA_DIR"), non_utf8())],
+        )
+        .unwrap_err();
+        assert_eq!(error_exit_code(&error), 6);
+        assert_eq!(
+            error.to_string(),
+            "WINE_ISO_RUN_DATA_DIR is not valid UTF-8."
+        );
+    }
+
+    #[test]
+    fn exit_codes_of_wine() {
+        let message = |code| fail(Failure::Wine(code), "wine is not succeed").to_string();
+        assert_eq!(Failure::Wine(Some(42)).exit_code(), 42);
+        assert_eq!(message(Some(42)), "wine is not succeed");
+        assert_eq!(Failure::Wine(Some(3)).exit_code(), 4);
+        assert_eq!(
+            message(Some(3)),
+            "wine is not succeed (exit code 3 of wine is reported as 4)"
+        );
+        assert_eq!(Failure::Wine(Some(256)).exit_code(), 4);
+        assert_eq!(Failure::Wine(None).exit_code(), 4);
+        assert_eq!(message(None), "wine is not succeed");
+    }
+
+    #[test]
+    fn base_env_of_exe_in_prefixes() {
+        let data_dir = test_dir("base-env");
+        let prefixes_dir = data_dir.join("prefixes");
+        let named_env_dir = data_dir.join("named").join("office");
+        let env_dir = prefixes_dir.join("setup.exe");
+        // the prefixes dir whose name is a part of another dir is not matched.
+        let other_env_dir = data_dir.join("prefixes-old").join("setup.exe");
+        for dir in [&named_env_dir, &env_dir, &other_env_dir] {
+            fs::create_dir_all(dir.join(".wine/drive_c/App")).unwrap();
+            fs::write(dir.join("conf.toml"), b"").unwrap();
+        }
+        let base_env = |dir: &Path| {
+            get_base_env_dir_from_exec_path(
+                dir.join(".wine/drive_c/App/app.exe"),
+                &data_dir,
+                &prefixes_dir,
+            )
+        };
+        assert_eq!(base_env(&env_dir), Some(env_dir.clone()));
+        assert_eq!(base_env(&named_env_dir), Some(named_env_dir.clone()));
+        assert_eq!(base_env(&other_env_dir), None);
+        fs::remove_file(env_dir.join("conf.toml")).unwrap();
+        assert_eq!(base_env(&env_dir), None);
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    /// Helper of `concurrent_updates_keep_both_tricks`, which runs in child processes since an
+    /// exec env is locked per process.
+    #[test]
+    #[ignore]
+    fn update_exec_env_in_child() {
+        let (Some(exec_env_path), Ok(trick)) =
+            (env::var_os("TEST_EXEC_ENV_PATH"), env::var("TEST_TRICK"))
+        else {
+            return;
+        };
+        let (exec_env_path, mut exec_conf, _) =
+            load_exec_env_at(PathBuf::from(exec_env_path)).unwrap();
+        // the other process reads conf.toml meanwhile without the lock.
+        thread::sleep(Duration::from_millis(300));
+        exec_conf.executed_tricks.push(trick);
+        save_exec_env(exec_env_path.join("conf.toml"), &exec_conf).unwrap();
+        unlock_exec_env();
+    }
+
+    #[test]
+    fn concurrent_updates_keep_both_tricks() {
+        let prefixes_dir = test_dir("concurrent-updates");
+        let exec_env_path = prefixes_dir.join("game.exe");
+        assert!(create_exec_env_dir(&exec_env_path).unwrap());
+        let children = ["corefonts", "vcrun2019"].map(|trick| {
+            Command::new(env::current_exe().unwrap())
+                .args(["--exact", "tests::update_exec_env_in_child", "--ignored"])
+                .env("TEST_EXEC_ENV_PATH", &exec_env_path)
+                .env("TEST_TRICK", trick)
+                .stdout(Stdio::null())
+                .spawn()
+                .unwrap()
+        });
+        for mut child in children {
+            assert!(child.wait().unwrap().success());
+        }
+        let exec_env_conf_path = exec_env_path.join("conf.toml");
+        let mut tricks =
+            parse_toml::<ExecEnv>(&fs::read(&exec_env_conf_path).unwrap(), &exec_env_conf_path)
+                .unwrap()
+                .executed_tricks;
+        tricks.sort();
+        assert_eq!(tricks, ["corefonts", "vcrun2019"]);
+        fs::remove_dir_all(&prefixes_dir).unwrap();
+    }
+
+    #[test]
+    fn lock_timeout_fails_as_locked() {
+        let prefixes_dir = test_dir("lock-timeout");
+        let exec_env_path = prefixes_dir.join("game.exe");
+        assert!(create_exec_env_dir(&exec_env_path).unwrap());
+        // another process keeps using the prefix.
+        let lock_file = File::create(exec_env_path.join("conf.lock")).unwrap();
+        lock_file.lock().unwrap();
+        LOCK_TIMEOUT.set(Duration::from_millis(200)).unwrap();
+        let started = Instant::now();
+        let error = lock_exec_env(&exec_env_path).unwrap_err();
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert_eq!(error_exit_code(&error), 8);
+        fs::remove_dir_all(&prefixes_dir).unwrap();
+    }
+
+    #[test]
+    fn runner_is_recorded_as_proton() {
+        let exec_conf = ExecEnv::default();
+        let toml = toml::to_string_pretty(&exec_conf).unwrap();
+        assert!(!toml.contains("proton"));
+        assert_eq!(
+            parse_toml::<ExecEnv>(toml.as_bytes(), "conf.toml")
+                .unwrap()
+                .runner
+                .get()
+                .kind(),
+            RunnerKind::Wine
+        );
+        let exec_conf = ExecEnv {
+            runner: PrefixRunner::Proton(Proton {
+                script: PathBuf::from("/opt/proton/proton"),
+            }),
+            ..Default::default()
+        };
+        let toml = toml::to_string_pretty(&exec_conf).unwrap();
+        assert!(toml.contains("proton = \"/opt/proton/proton\""));
+        assert_eq!(
+            parse_toml::<ExecEnv>(toml.as_bytes(), "conf.toml")
+                .unwrap()
+                .runner,
+            exec_conf.runner
+        );
+    }
+
+    #[test]
+    fn download_conflicts_with_exe_path() {
+        let cli =
+            Cli::try_parse_from([APP_NAME, "--download", "https://example.com/setup.exe"]).unwrap();
+        assert_eq!(cli.run.exec_path, None);
+        let error = Cli::try_parse_from([
+            APP_NAME,
+            "--download",
+            "https://example.com/setup.exe",
+            "game.exe",
+        ])
+        .unwrap_err();
+        assert_eq!(error.kind(), clap::error::ErrorKind::ArgumentConflict);
+    }
+
+    /// Shell link with the link info of `target` and unicode strings of relative path,
+    /// working dir and arguments.
+    fn shell_link_data(target: (&str, &str), strings: [&str; 3]) -> Vec<u8> {
+        let mut data = vec![0; 0x4c];
+        data[..4].copy_from_slice(&0x4cu32.to_le_bytes());
+        // HasLinkInfo, HasRelativePath, HasWorkingDir, HasArguments and IsUnicode.
+        data[0x14..0x18].copy_from_slice(&(0x2u32 | 0x8 | 0x10 | 0x20 | 0x80).to_le_bytes());
+        let mut link_info = vec![0; 28];
+        link_info[8..12].copy_from_slice(&1u32.to_le_bytes());
+        link_info[16..20].copy_from_slice(&28u32.to_le_bytes());
+        link_info.extend(target.0.as_bytes());
+        link_info.push(0);
+        let suffix_offset = link_info.len() as u32;
+        link_info[24..28].copy_from_slice(&suffix_offset.to_le_bytes());
+        link_info.extend(target.1.as_bytes());
+        link_info.push(0);
+        let size = link_info.len() as u32;
+        link_info[..4].copy_from_slice(&size.to_le_bytes());
+        data.extend(link_info);
+        for string in strings {
+            let units = string.encode_utf16().collect::<Vec<_>>();
+            data.extend((units.len() as u16).to_le_bytes());
+            data.extend(units.iter().flat_map(|unit| unit.to_le_bytes()));
+        }
+        data
+    }
+
+    #[test]
+    fn parse_shell_link_fields() {
+        let dir = test_dir("shell-link");
+        let path = dir.join("game.lnk");
+        fs::write(
+            &path,
+            shell_link_data(
+                (r"C:\Games\", "game.exe"),
+                [r".\game.exe", r"C:\Games", "-w \"save 1\""],
+            ),
+        )
+        .unwrap();
+        let shell_link = parse_shell_link(&path).unwrap();
+        assert_eq!(shell_link.target.as_deref(), Some(r"C:\Games\game.exe"));
+        assert_eq!(shell_link.relative_path.as_deref(), Some(r".\game.exe"));
+        assert_eq!(shell_link.working_dir.as_deref(), Some(r"C:\Games"));
+        assert_eq!(shell_link.arguments.as_deref(), Some("-w \"save 1\""));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parse_broken_shell_links() {
+        let dir = test_dir("broken-shell-link");
+        let path = dir.join("broken.lnk");
+        let data = shell_link_data((r"C:\", "game.exe"), ["a", "b", "c"]);
+        let parse = |data: &[u8]| {
+            fs::write(&path, data).unwrap();
+            parse_shell_link(&path)
+        };
+        assert!(parse(&[]).is_err());
+        assert!(parse(b"MZ").is_err());
+        // truncated in the header, the link info and the strings.
+        for len in [0x20, 0x4c, 0x60, data.len() - 1] {
+            assert!(parse(&data[..len]).is_err(), "{len}");
+        }
+        // offsets of the link info which point outside of the file.
+        for field in [0, 16, 24] {
+            let mut data = data.clone();
+            data[0x4c + field..0x4c + field + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+            assert!(parse(&data).is_err(), "{field}");
+        }
+        // the local base path which is not terminated.
+        let mut data = data.clone();
+        data.truncate(0x4c + 28 + 3);
+        assert!(parse(&data).is_err());
+        // a string which is longer than the file.
+        let mut data = shell_link_data((r"C:\", "game.exe"), ["a", "b", "c"]);
+        let len = data.len();
+        data[len - 4..len - 2].copy_from_slice(&u16::MAX.to_le_bytes());
+        assert!(parse(&data).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn read_pe_info_of_headers_and_manifest() {
+        let dir = test_dir("pe-info");
+        let path = dir.join("game.exe");
+        fs::write(&path, pe_data(0x10b)).unwrap();
+        let info = read_pe_info(&path).unwrap();
+        assert_eq!((info.machine.as_str(), info.bits), ("x86", 32));
+        assert_eq!(info.execution_level, None);
+        fs::write(
+            &path,
+            pe_with_manifest(
+                "<assembly><requestedExecutionLevel level='requireAdministrator' uiAccess='false'/>\
+                 <supportedOS Id=\"{8e0f7a12-bfb3-4fe8-b9a5-48fd50a15a9a}\"/>\
+                 <supportedOS Id=\"{00000000-0000-0000-0000-000000000000}\"/></assembly>",
+            ),
+        )
+        .unwrap();
+        let info = read_pe_info(&path).unwrap();
+        assert_eq!((info.machine.as_str(), info.bits), ("x86_64", 64));
+        assert_eq!(
+            info.execution_level.as_deref(),
+            Some("requireAdministrator")
+        );
+        assert_eq!(
+            info.supported_os,
+            ["Windows 10", "{00000000-0000-0000-0000-000000000000}"]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn read_pe_info_of_broken_headers() {
+        let dir = test_dir("broken-pe-info");
+        let path = dir.join("broken.exe");
+        let read = |exe: &[u8]| {
+            fs::write(&path, exe).unwrap();
+            read_pe_info(&path)
+        };
+        let exe = pe_with_manifest("<assembly/>");
+        assert!(read(&exe).is_ok());
+        for len in [0, 0x3c, 0x86, 0x99, 0x110] {
+            assert!(read(&exe[..len]).is_err(), "{len}");
+        }
+        // the PE header offset which points outside of the file.
+        let mut broken = exe.clone();
+        put_u32(&mut broken, 0x3c, u32::MAX);
+        assert!(read(&broken).is_err());
+        // unknown optional header magic.
+        let mut broken = exe.clone();
+        broken[0x98..0x9a].copy_from_slice(&0x30bu16.to_le_bytes());
+        assert!(read(&broken).is_err());
+        // the end of the section overflows, and it does not have the resources.
+        let section = 0x98 + 240;
+        let mut broken = exe.clone();
+        put_u32(&mut broken, 0x118, 0xffff_ff80);
+        put_u32(&mut broken, section + 12, 0xffff_ff00);
+        let info = read(&broken).unwrap();
+        assert_eq!(info.execution_level, None);
+        // the file offset of the resources overflows.
+        let mut broken = exe.clone();
+        put_u32(&mut broken, 0x118, 0x1100);
+        put_u32(&mut broken, section + 20, u32::MAX - 0x10);
+        assert!(read(&broken).is_err());
+        // the manifest which is outside of the resources.
+        for (offset, value) in [(0x200 + 0x48, 0x10), (0x200 + 0x4c, u32::MAX)] {
+            let mut broken = exe.clone();
+            put_u32(&mut broken, offset, value);
+            assert!(read(&broken).is_err(), "{offset:x}");
+        }
+        // the directory entry which points outside of the resources.
+        let mut broken = exe.clone();
+        put_u32(&mut broken, 0x200 + 20, 0x8fff_ffff);
+        assert!(read(&broken).is_err());
+        // a truncated section.
+        assert!(read(&exe[..0x230]).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parse_wine_versions() {
+        let version = |numbers: &[u64], rc| WineVersion {
+            numbers: numbers.to_vec(),
+            rc,
+        };
+        assert_eq!(
+            WineVersion::parse("wine-9.0 (Staging)"),
+            Some(version(&[9, 0], None))
+        );
+        assert_eq!(
+            WineVersion::parse("9.0-rc2"),
+            Some(version(&[9, 0], Some(2)))
+        );
+        assert_eq!(
+            WineVersion::parse(" wine-8.0.2\n"),
+            Some(version(&[8, 0, 2], None))
+        );
+        assert_eq!(WineVersion::parse("10"), Some(version(&[10], None)));
+        assert_eq!(
+            WineVersion::parse("wine-9.0x86"),
+            Some(version(&[9, 0], None))
+        );
+        for text in ["", "wine-", "Staging", "9..0", ".9", "9."] {
+            assert_eq!(WineVersion::parse(text), None, "{text}");
+        }
+        // too large numbers are not versions.
+        assert_eq!(WineVersion::parse("99999999999999999999.0"), None);
+
+        let compare = |a: &str, b: &str| {
+            WineVersion::parse(a)
+                .unwrap()
+                .compare(&WineVersion::parse(b).unwrap())
+        };
+        assert_eq!(compare("9.0", "9"), Ordering::Equal);
+        assert_eq!(compare("9.0-rc2", "9.0"), Ordering::Less);
+        assert_eq!(compare("9.0-rc2", "9.0-rc10"), Ordering::Less);
+        assert_eq!(compare("10.0", "9.22"), Ordering::Greater);
+        assert_eq!(compare("8.0.2", "8.0"), Ordering::Greater);
+    }
+
+    #[test]
+    fn parse_and_check_version_constraints() {
+        let satisfied = |constraint: &str, version: &str| {
+            parse_version_constraint(constraint)
+                .unwrap()
+                .is_satisfied(&WineVersion::parse(version).unwrap())
+        };
+        assert!(satisfied(">=9.0,<10", "9.5"));
+        assert!(!satisfied(">=9.0,<10", "10.0"));
+        assert!(!satisfied(">=9.0,<10", "9.0-rc2"));
+        // a release candidate comes before the release.
+        assert!(satisfied(">=9.0,<10", "10.0-rc1"));
+        assert!(satisfied("9.0", "9.1"));
+        assert!(!satisfied("9.0", "8.21"));
+        assert!(satisfied(" == 9.0 , <= 9.0 ", "9"));
+        assert!(satisfied("=9.0", "9.0"));
+        assert!(satisfied(">8", "8.0.1"));
+        for constraint in ["", " ", ",", ">=9.0,", "=>9", ">=", "<abc", "!=9.0"] {
+            assert!(
+                parse_version_constraint(constraint).is_err(),
+                "{constraint}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_dll_overrides() {
+        let parse = |s: &str| parse_dll_override(s).map(|(dll, mode)| format!("{dll}={mode}"));
+        assert_eq!(parse("d3d9=n,b").unwrap(), "d3d9=n,b");
+        assert_eq!(parse("d3d9=builtin,native").unwrap(), "d3d9=b,n");
+        assert_eq!(parse("dxgi=native").unwrap(), "dxgi=n");
+        assert_eq!(parse("mscoree=").unwrap(), "mscoree=");
+        assert_eq!(parse("mscoree=disabled").unwrap(), "mscoree=");
+        assert_eq!(parse("winmm=d").unwrap(), "winmm=");
+        // the mode is separated by the first =.
+        assert!(parse("d3d9=n=b").is_err());
+        for s in [
+            "d3d9",
+            "=n",
+            "a;b=n",
+            "a,b=n",
+            "d3d9=n,n",
+            "d3d9=n,native",
+            "d3d9=,n",
+            "d3d9=d,b",
+            "d3d9=x",
+            "d3d9=N",
+        ] {
+            assert!(parse(s).is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn glob_match_patterns() {
+        assert!(glob_match("", ""));
+        assert!(!glob_match("", "WINEDEBUG"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("**", "WINEDEBUG"));
+        assert!(glob_match("WINEDEBUG", "WINEDEBUG"));
+        assert!(!glob_match("WINEDEBUG", "WINEDEBUGX"));
+        assert!(glob_match("WINE*", "WINE"));
+        assert!(glob_match("WINE*", "WINEDEBUG"));
+        assert!(glob_match("*DEBUG", "WINEDEBUG"));
+        assert!(glob_match("*_*_*", "XDG_RUNTIME_DIR"));
+        assert!(glob_match("W?NE*", "WINEDLLOVERRIDES"));
+        assert!(!glob_match("?", ""));
+        assert!(!glob_match("WINE?", "WINE"));
+        // the star is tried again after a partial match.
+        assert!(glob_match("*AB", "AAAB"));
+        assert!(glob_match("A*B*C", "ABXBYC"));
+        assert!(!glob_match("A*B*C", "ABXBY"));
+        assert!(!glob_match("*A", "BBB"));
+        // patterns are case sensitive.
+        assert!(!glob_match("wine*", "WINEDEBUG"));
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("corefonts", "corefonts"), 0);
+        assert_eq!(edit_distance("abc", ""), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("sitting", "kitten"), 3);
+        assert_eq!(edit_distance("corefont", "corefonts"), 1);
+        assert_eq!(edit_distance("vcrun2017", "vcrun2019"), 1);
+        assert_eq!(edit_distance("d3dx9", "dx3d9"), 2);
+        // characters are compared instead of bytes.
+        assert_eq!(edit_distance("é", "e"), 1);
+    }
+}